@@ -0,0 +1,303 @@
+/* See LICENSE for license details */
+use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+extern crate chrono;
+use chrono::prelude::*;
+
+use crate::form::Form;
+
+/// What a listing can be sorted by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    /// Returns the name of the key as used in the query string
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+        }
+    }
+}
+
+/// How a listing should be shown, as asked for in the query string with
+/// `sort` (`name`, `size` or `modified`), `order` (`asc` or `desc`) and
+/// `format` (`html` or `json`)
+///
+/// # Examples
+///
+/// ```
+/// use server::{ListingQuery, SortKey};
+///
+/// let query = ListingQuery::parse(Some("sort=size&order=desc"));
+/// assert_eq!(query.sort, SortKey::Size);
+/// assert!(query.descending);
+/// assert!(!query.json);
+///
+/// let query = ListingQuery::parse(None);
+/// assert_eq!(query.sort, SortKey::Name);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ListingQuery {
+    pub sort: SortKey,
+    pub descending: bool,
+    pub json: bool,
+}
+
+impl ListingQuery {
+    /// Function to read the options from a query string. Anything unknown
+    /// is ignored
+    pub fn parse(query: Option<&str>) -> ListingQuery {
+        let form = query
+            .and_then(|query| Form::parse_urlencoded(query.as_bytes()).ok())
+            .unwrap_or_default();
+        let sort = match form.get("sort") {
+            Some("size") => SortKey::Size,
+            Some("modified") => SortKey::Modified,
+            _ => SortKey::Name,
+        };
+        ListingQuery {
+            sort,
+            descending: form.get("order") == Some("desc"),
+            json: form.get("format") == Some("json"),
+        }
+    }
+}
+
+/// One file or directory in a listing
+#[derive(Clone, Debug)]
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// The contents of a directory, ready to be sent as a page or as JSON
+#[derive(Clone, Debug)]
+pub struct Listing {
+    path: String,
+    entries: Vec<Entry>,
+}
+
+impl Listing {
+    /// Function to read a directory. `request_path` is the path the client
+    /// asked for, which the links are made from, and only the names
+    /// `visible` returns true for are listed
+    ///
+    /// # Errors
+    ///
+    /// If the directory could not be read
+    pub fn read<F>(
+        dir: &Path,
+        request_path: &str,
+        visible: F,
+    ) -> io::Result<Listing>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !visible(&name) {
+                continue;
+            }
+            // Follow symlinks, so a link to a directory is listed as one
+            let metadata = match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            entries.push(Entry {
+                name,
+                is_dir: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                modified: metadata.modified().ok(),
+            });
+        }
+        let mut path = request_path.to_string();
+        if !path.ends_with('/') {
+            path.push('/');
+        }
+        let mut listing = Listing { path, entries };
+        listing.sort(SortKey::Name, false);
+        Ok(listing)
+    }
+
+    /// Function to sort the listing. Directories always come first
+    pub fn sort(&mut self, key: SortKey, descending: bool) {
+        self.entries.sort_by(|a, b| {
+            let order = match key {
+                SortKey::Name => Ordering::Equal,
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::Modified => a.modified.cmp(&b.modified),
+            }
+            .then_with(|| a.name.cmp(&b.name));
+            let order = if descending { order.reverse() } else { order };
+            b.is_dir.cmp(&a.is_dir).then(order)
+        });
+    }
+
+    /// Function to render the listing as a html page, with links to sort it
+    /// by each column
+    pub fn to_html(&self, query: ListingQuery) -> String {
+        let title = format!("Index of {}", escape_html(&self.path));
+        let mut html = format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+             <title>{0}</title></head><body><h1>{0}</h1><table>\
+             <tr>{1}{2}{3}</tr>",
+            title,
+            sort_link("Name", SortKey::Name, query),
+            sort_link("Size", SortKey::Size, query),
+            sort_link("Last modified", SortKey::Modified, query),
+        );
+        if self.path != "/" {
+            html.push_str(
+                "<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>",
+            );
+        }
+        for entry in &self.entries {
+            let slash = if entry.is_dir { "/" } else { "" };
+            let size = if entry.is_dir {
+                String::from("-")
+            } else {
+                entry.size.to_string()
+            };
+            let modified = entry.modified.map_or(String::new(), |time| {
+                DateTime::<Utc>::from(time)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            });
+            html.push_str(&format!(
+                "<tr><td><a href=\"{}{}{}\">{}{}</a></td>\
+                 <td>{}</td><td>{}</td></tr>",
+                escape_html(&self.path),
+                escape_html(&encode_segment(&entry.name)),
+                slash,
+                escape_html(&entry.name),
+                slash,
+                size,
+                modified
+            ));
+        }
+        html.push_str("</table></body></html>");
+        html
+    }
+
+    /// Function to render the listing as a JSON array of objects with the
+    /// `name`, `type` (`file` or `directory`), `size` and `modified` (in RFC
+    /// 3339 format) of every entry
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let listing = server::Listing::read(
+    ///     std::path::Path::new("public/styles"),
+    ///     "/styles",
+    ///     |_| true,
+    /// )
+    /// .unwrap();
+    ///
+    /// let json = listing.to_json();
+    /// assert!(
+    ///     json.starts_with("[{\"name\":\"styles.css\",\"type\":\"file\"")
+    /// );
+    /// ```
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let modified =
+                    entry.modified.map_or(String::from("null"), |time| {
+                        json_string(&DateTime::<Utc>::from(time).to_rfc3339())
+                    });
+                format!(
+                    "{{\"name\":{},\"type\":\"{}\",\"size\":{},\
+                     \"modified\":{}}}",
+                    json_string(&entry.name),
+                    if entry.is_dir { "directory" } else { "file" },
+                    entry.size,
+                    modified
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(","))
+    }
+}
+
+/// Function to make the header of a column, linking to the listing sorted
+/// by it. Clicking the column it is sorted by already reverses the order
+fn sort_link(label: &str, key: SortKey, query: ListingQuery) -> String {
+    let descending = query.sort == key && !query.descending;
+    format!(
+        "<th><a href=\"?sort={}&amp;order={}\">{}</a></th>",
+        key.as_str(),
+        if descending { "desc" } else { "asc" },
+        label
+    )
+}
+
+/// Function to percent encode a file name so it can be used in a path
+pub(crate) fn encode_segment(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Function to escape text so it can go in html
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Function to quote text as a JSON string
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                quoted.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -1,11 +1,358 @@
 /* See LICENSE for license details */
-use server::Server;
-
-fn main() -> std::io::Result<()> {
-    let server = Server::new(5);
-    server
-        .start_at("0.0.0.0:8080", "config.txt")
-        .join()
-        .unwrap();
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::process;
+
+extern crate libc;
+
+use server::{Config, LogLevel, Parse, Server};
+
+/// The exit code for a config that cannot be used or a server that failed
+const EXIT_FAILURE: i32 = 1;
+/// The exit code for command-line arguments that do not make sense
+const EXIT_USAGE: i32 = 2;
+
+/// Printed for `help`, with the name the program was run as filled in
+const USAGE: &str = "\
+Usage: {program} [COMMAND] [OPTIONS]
+
+Commands:
+    serve                  Run the server (the default)
+    check-config           Check the config without starting the server,
+                           and print it with every default filled in
+    version                Print the version and exit
+    help                   Print this help and exit
+
+Options:
+    -c, --config FILE      Read the config from FILE (default config.txt)
+    -b, --bind ADDRESS     Listen on ADDRESS (like 0.0.0.0:8080) instead of
+                           the addresses in the config. Can be given more
+                           than once
+    -w, --workers NUMBER   Handle NUMBER requests at once
+    -r, --root DIR         Serve files from DIR
+    -l, --log-level LEVEL  Print error, warn, info or debug messages
+    -d, --daemon           Run in the background, away from the terminal
+    -h, --help             Print this help and exit
+    -V, --version          Print the version and exit
+
+Options win over the config, and stay in place when it is reloaded.
+
+Signals:
+    TERM, INT              Finish the requests being handled and exit. A
+                           second one exits straight away
+    HUP                    Read the config again
+    USR1                   Open the access log again, after rotating it
+";
+
+/// What the program was asked to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    Serve,
+    CheckConfig,
+    Version,
+    Help,
+}
+
+/// Everything given on the command line
+#[derive(Debug)]
+struct Options {
+    command: Command,
+    config: String,
+    bind: Vec<String>,
+    workers: Option<usize>,
+    root: Option<String>,
+    log_level: Option<LogLevel>,
+    daemon: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            command: Command::Serve,
+            config: String::from("config.txt"),
+            bind: Vec::new(),
+            workers: None,
+            root: None,
+            log_level: None,
+            daemon: false,
+        }
+    }
+}
+
+fn main() {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| String::from("server"));
+    let options = parse_args(args).unwrap_or_else(|err| {
+        eprintln!("{}: {}", program, err);
+        eprintln!("Try `{} help` for more information", program);
+        process::exit(EXIT_USAGE);
+    });
+
+    match options.command {
+        Command::Help => print!("{}", USAGE.replace("{program}", &program)),
+        Command::Version => {
+            println!("rust_server {}", env!("CARGO_PKG_VERSION"))
+        }
+        Command::CheckConfig => check_config(&options),
+        Command::Serve => {
+            let config = load(&options);
+            if options.daemon {
+                // Nothing can be reported once in the background, so the
+                // addresses are tried while it still can be
+                check_addresses(&config)
+                    .unwrap_or_else(|err| fail("could not listen", err));
+                daemonize()
+                    .unwrap_or_else(|err| fail("could not daemonize", err));
+            }
+            let mut server = Server::new(config.workers);
+            server.handle_signals();
+            let handle = server
+                .start(config)
+                .unwrap_or_else(|err| fail("could not start", err));
+            handle.join();
+        }
+    }
+}
+
+/// Function to read the command-line arguments, without the program name
+fn parse_args<I: Iterator<Item = String>>(
+    mut args: I,
+) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut command = None;
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            if let Some(command) = command {
+                return Err(format!(
+                    "unexpected `{}` after `{}`",
+                    arg,
+                    command_name(command)
+                ));
+            }
+            command = Some(match arg.as_str() {
+                "serve" => Command::Serve,
+                "check-config" => Command::CheckConfig,
+                "version" => Command::Version,
+                "help" => Command::Help,
+                _ => return Err(format!("unknown command `{}`", arg)),
+            });
+            continue;
+        }
+
+        // Values can be given as `--option value` or `--option=value`
+        let (name, mut value) = match arg.find('=') {
+            Some(equals) if arg.starts_with("--") => {
+                (&arg[..equals], Some(arg[equals + 1..].to_string()))
+            }
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |args: &mut I| {
+            value
+                .take()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("`{}` needs a value", name))
+        };
+        match name {
+            "-c" | "--config" => options.config = value(&mut args)?,
+            "-b" | "--bind" => options.bind.push(value(&mut args)?),
+            "-w" | "--workers" => {
+                let workers = value(&mut args)?;
+                options.workers = match workers.parse::<usize>() {
+                    Ok(workers) if workers > 0 => Some(workers),
+                    _ => {
+                        return Err(format!(
+                            "`{}` needs a number above zero, not `{}`",
+                            name, workers
+                        ))
+                    }
+                };
+            }
+            "-r" | "--root" => options.root = Some(value(&mut args)?),
+            "-l" | "--log-level" => {
+                let level = value(&mut args)?;
+                options.log_level = match LogLevel::from_name(&level) {
+                    Some(level) => Some(level),
+                    None => {
+                        return Err(format!(
+                            "`{}` needs error, warn, info or debug, not `{}`",
+                            name, level
+                        ))
+                    }
+                };
+            }
+            "-d" | "--daemon" => options.daemon = true,
+            "-h" | "--help" => command = Some(Command::Help),
+            "-V" | "--version" => command = Some(Command::Version),
+            _ => return Err(format!("unknown option `{}`", name)),
+        }
+        if arg.starts_with("--") && arg.contains('=') && !takes_value(name) {
+            return Err(format!("`{}` does not take a value", name));
+        }
+    }
+    options.command = command.unwrap_or(Command::Serve);
+    Ok(options)
+}
+
+/// Returns whether an option is followed by a value
+fn takes_value(name: &str) -> bool {
+    matches!(
+        name,
+        "--config" | "--bind" | "--workers" | "--root" | "--log-level"
+    )
+}
+
+/// Returns the name a command is given as
+fn command_name(command: Command) -> &'static str {
+    match command {
+        Command::Serve => "serve",
+        Command::CheckConfig => "check-config",
+        Command::Version => "version",
+        Command::Help => "help",
+    }
+}
+
+/// Function to read the config and put the options from the command line
+/// over it, checking that the server can be made from it. Exits if it
+/// cannot
+fn load(options: &Options) -> Config {
+    let mut config = Config::load(&options.config).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(EXIT_FAILURE);
+    });
+    let mut overrides = Vec::new();
+    if !options.bind.is_empty() {
+        let addresses = options.bind.iter().map(|address| quote(address));
+        let value = format!("[{}]", addresses.collect::<Vec<_>>().join(", "));
+        overrides.push(("--bind", "server.listen", value));
+    }
+    if let Some(workers) = options.workers {
+        overrides.push(("--workers", "server.workers", workers.to_string()));
+    }
+    if let Some(root) = &options.root {
+        overrides.push(("--root", "server.root", quote(root)));
+    }
+    if let Some(level) = options.log_level {
+        overrides.push(("--log-level", "logging.level", quote(level.as_str())));
+    }
+    for (option, key, value) in overrides {
+        config.set(key, &value).unwrap_or_else(|err| {
+            eprintln!("{}: {}", option, err.message());
+            process::exit(EXIT_FAILURE);
+        });
+    }
+    // Reads the error pages, mime types and document root the config names
+    if let Err(err) = Parse::from_config(&config) {
+        eprintln!("{}", err);
+        process::exit(EXIT_FAILURE);
+    }
+    config
+}
+
+/// Function to check a config without starting the server. On top of what
+/// `load()` checks, the addresses are tried and anything that looks like a
+/// mistake is warned about. The config is printed the way the server would
+/// use it, and the program exits with `EXIT_FAILURE` if it could not start
+fn check_config(options: &Options) {
+    let config = load(options);
+    let name = config_name(&config);
+    let mut warnings = config.warnings();
+    let mut errors = 0;
+    for address in &config.listen {
+        match TcpListener::bind(address) {
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => warnings
+                .push(format!(
+                    "{} is already in use, which is fine if it is by this \
+                     server",
+                    address
+                )),
+            Err(err) => {
+                eprintln!("{}: cannot listen on {}: {}", name, address, err);
+                errors += 1;
+            }
+        }
+    }
+    for warning in &warnings {
+        eprintln!("{}: warning: {}", name, warning);
+    }
+    print!("{}", config);
+    if errors > 0 {
+        eprintln!("{} cannot be used", name);
+        process::exit(EXIT_FAILURE);
+    }
+    match warnings.len() {
+        0 => eprintln!("{} is valid", name),
+        1 => eprintln!("{} is valid, with 1 warning", name),
+        count => eprintln!("{} is valid, with {} warnings", name, count),
+    }
+}
+
+/// Function to write a string the way it is written in the config file
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Returns the name of the file a config was read from
+fn config_name(config: &Config) -> String {
+    config
+        .path()
+        .map_or(String::from("config"), |path| path.display().to_string())
+}
+
+/// Function to check that every address in a config can be listened on
+fn check_addresses(config: &Config) -> io::Result<()> {
+    for address in &config.listen {
+        TcpListener::bind(address).map_err(|err| {
+            io::Error::new(err.kind(), format!("{}: {}", address, err))
+        })?;
+    }
+    Ok(())
+}
+
+/// Function to report an error that stops the server and exit
+fn fail(doing: &str, err: io::Error) -> ! {
+    eprintln!("{}: {}", doing, err);
+    process::exit(EXIT_FAILURE);
+}
+
+/// Function to carry on in a new process that is not tied to the terminal,
+/// leaving the one that was started behind. Has to be called before any
+/// threads are started, as only the calling thread is carried over
+#[cfg(unix)]
+fn daemonize() -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::os::unix::io::AsRawFd;
+
+    // Safe as no other threads are running yet, and the descriptors passed
+    // to dup2() are open
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            _ => process::exit(0),
+        }
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let null = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        for fd in 0..3 {
+            if libc::dup2(null.as_raw_fd(), fd) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
     Ok(())
 }
+
+/// Function to carry on in the background, which is not supported here
+#[cfg(not(unix))]
+fn daemonize() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "running in the background is only supported on unix",
+    ))
+}
@@ -0,0 +1,221 @@
+/* See LICENSE for license details */
+use std::fmt;
+use std::io::prelude::*;
+
+extern crate brotli;
+extern crate flate2;
+use flate2::read::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+
+use crate::mime;
+
+/// Files smaller than this many bytes are not worth compressing
+pub const DEFAULT_MIN_SIZE: u64 = 1024;
+
+/// How hard brotli tries. The highest levels are far too slow to use on
+/// every request
+const BROTLI_QUALITY: u32 = 5;
+/// The window size brotli uses, as a power of two
+const BROTLI_WINDOW: u32 = 22;
+
+/// How many bytes brotli reads from the file at a time
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Types that are compressible even though they are not text
+const COMPRESSIBLE: &[&str] = &["application/wasm", "image/x-icon"];
+
+/// The content codings a response can be sent in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// The codings the server can compress with, best first
+    pub const ALL: [Encoding; 3] =
+        [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    /// Returns the name of the coding as used in Accept-Encoding and
+    /// Content-Encoding
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    /// Returns the extension of files stored in the coding, as found next to
+    /// the files they were made from
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Encoding::Brotli => Some(".br"),
+            Encoding::Gzip => Some(".gz"),
+            Encoding::Identity | Encoding::Deflate => None,
+        }
+    }
+
+    /// Function to get a coding from its name. Returns None for codings the
+    /// server does not know
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.trim().to_lowercase().as_str() {
+            "identity" => Some(Encoding::Identity),
+            "br" => Some(Encoding::Brotli),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Function to pick the coding to send a response in from the value of an
+/// Accept-Encoding header. Only the codings in `available` are considered,
+/// and when the client likes several equally the first of them wins. The
+/// uncompressed response is chosen if the client says it prefers it or
+/// accepts nothing else. Returns None if the client accepts none of them and
+/// refuses an uncompressed response too (with `identity;q=0` or `*;q=0`)
+///
+/// # Examples
+///
+/// ```
+/// use server::Encoding;
+///
+/// let available = Encoding::ALL;
+/// assert_eq!(
+///     server::negotiate_encoding("gzip, br", &available),
+///     Some(Encoding::Brotli)
+/// );
+/// assert_eq!(
+///     server::negotiate_encoding("br;q=0.5, gzip;q=0.8", &available),
+///     Some(Encoding::Gzip)
+/// );
+/// assert_eq!(
+///     server::negotiate_encoding("compress", &available),
+///     Some(Encoding::Identity)
+/// );
+/// assert_eq!(
+///     server::negotiate_encoding("gzip;q=5, br", &available),
+///     Some(Encoding::Brotli)
+/// );
+/// assert_eq!(server::negotiate_encoding("*;q=0", &available), None);
+/// assert_eq!(
+///     server::negotiate_encoding("compress, identity;q=0", &available),
+///     None
+/// );
+/// ```
+pub fn negotiate_encoding(
+    accept: &str,
+    available: &[Encoding],
+) -> Option<Encoding> {
+    let mut accepted = Vec::new();
+    let mut wildcard = None;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim().to_lowercase();
+        if name.is_empty() {
+            continue;
+        }
+        let quality = params
+            .filter_map(|param| {
+                let (key, value) = param.split_at(param.find('=')?);
+                if key.trim().eq_ignore_ascii_case("q") {
+                    value[1..]
+                        .trim()
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|quality| !quality.is_nan())
+                } else {
+                    None
+                }
+            })
+            .next()
+            // Out of range qualities count as the nearest one there is
+            .map_or(1.0, |quality| quality.clamp(0.0, 1.0));
+        if name == "*" {
+            wildcard = Some(quality);
+        } else if let Some(encoding) = Encoding::from_name(&name) {
+            accepted.push((encoding, quality));
+        }
+    }
+    let quality_of = |encoding: Encoding| {
+        accepted
+            .iter()
+            .find(|(accepted, _)| *accepted == encoding)
+            .map(|(_, quality)| *quality)
+            .or(wildcard)
+    };
+
+    // Clients that do not mention identity accept it, but would rather
+    // have any compression they listed
+    let identity = quality_of(Encoding::Identity);
+    let mut best = (Encoding::Identity, 0.0);
+    for encoding in available {
+        let quality = quality_of(*encoding).unwrap_or(0.0);
+        if quality > best.1 {
+            best = (*encoding, quality);
+        }
+    }
+    if best.1 > 0.0 && identity.is_none_or(|identity| best.1 >= identity) {
+        Some(best.0)
+    } else if identity == Some(0.0) {
+        None
+    } else {
+        Some(Encoding::Identity)
+    }
+}
+
+/// Function to check if a content type is worth compressing. Text is, while
+/// most images, audio, video and archives are compressed already
+///
+/// # Examples
+///
+/// ```
+/// assert!(server::is_compressible("text/html; charset=utf-8"));
+/// assert!(server::is_compressible("image/svg+xml"));
+/// assert!(!server::is_compressible("image/png"));
+/// ```
+pub fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    mime::is_text(content_type)
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || COMPRESSIBLE.contains(&essence.to_lowercase().as_str())
+}
+
+/// Function to compress everything read from `reader` as it is read. The
+/// compressed length is not known until the end, so the result is sent in
+/// the chunked transfer coding
+pub(crate) fn compress_reader<R>(
+    reader: R,
+    encoding: Encoding,
+) -> Box<dyn Read + Send>
+where
+    R: Read + Send + 'static,
+{
+    match encoding {
+        Encoding::Identity => Box::new(reader),
+        Encoding::Brotli => Box::new(brotli::CompressorReader::new(
+            reader,
+            BUFFER_SIZE,
+            BROTLI_QUALITY,
+            BROTLI_WINDOW,
+        )),
+        Encoding::Gzip => {
+            Box::new(GzEncoder::new(reader, Compression::default()))
+        }
+        // The deflate coding is really the zlib format, not raw deflate
+        Encoding::Deflate => {
+            Box::new(ZlibEncoder::new(reader, Compression::default()))
+        }
+    }
+}
@@ -0,0 +1,127 @@
+/* See LICENSE for license details */
+use std::fs::Metadata;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http_date::{parse_http_date, whole_seconds};
+use crate::request::{Method, Request};
+
+/// What the conditional headers of a request say should happen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precondition {
+    /// Send the response as usual
+    Proceed,
+    /// The client already has the current file, so send 304 Not Modified
+    NotModified,
+    /// A condition the client set does not hold, so send 412 Precondition
+    /// Failed
+    Failed,
+}
+
+/// Function to make a strong entity tag for a file from its length and
+/// modification time, so the file never has to be read to get it. Any write
+/// to the file changes its modification time and so its tag
+///
+/// # Examples
+///
+/// ```
+/// let metadata = std::fs::metadata("Cargo.toml").unwrap();
+///
+/// let etag = server::file_etag(&metadata);
+/// assert!(etag.starts_with('"') && etag.ends_with('"'));
+/// assert_eq!(etag, server::file_etag(&metadata));
+/// ```
+pub fn file_etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!(
+        "\"{:x}-{:x}{:08x}\"",
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    )
+}
+
+/// Function to evaluate If-Match, If-Unmodified-Since, If-None-Match and
+/// If-Modified-Since against the current entity tag and modification time of
+/// a file, in the order RFC 7232 gives. Dates that cannot be parsed are
+/// ignored, as are the date headers when the matching tag header is present
+///
+/// # Examples
+///
+/// ```
+/// use server::{Precondition, Request};
+///
+/// let text =
+///     "GET / HTTP/1.1\r\nHost: a\r\nIf-None-Match: \"a\", W/\"b\"\r\n\r\n";
+/// let request = Request::read_from(&mut text.as_bytes(), 0).unwrap();
+///
+/// let result = server::check_preconditions(&request, "\"b\"", None);
+/// assert_eq!(result, Precondition::NotModified);
+///
+/// let result = server::check_preconditions(&request, "\"c\"", None);
+/// assert_eq!(result, Precondition::Proceed);
+/// ```
+pub fn check_preconditions(
+    request: &Request,
+    etag: &str,
+    modified: Option<SystemTime>,
+) -> Precondition {
+    let modified = modified.map(whole_seconds);
+
+    if let Some(value) = request.header("If-Match") {
+        if !tag_list_matches(value, etag, true) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = request
+        .header("If-Unmodified-Since")
+        .and_then(parse_http_date)
+    {
+        if modified.is_none_or(|modified| modified > since) {
+            return Precondition::Failed;
+        }
+    }
+
+    let is_read = matches!(request.method(), Method::Get | Method::Head);
+    if let Some(value) = request.header("If-None-Match") {
+        if tag_list_matches(value, etag, false) {
+            return if is_read {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if let Some(since) = request
+        .header("If-Modified-Since")
+        .and_then(parse_http_date)
+    {
+        if is_read && modified.is_some_and(|modified| modified <= since) {
+            return Precondition::NotModified;
+        }
+    }
+    Precondition::Proceed
+}
+
+/// Function to check if a header holding `*` or a list of entity tags
+/// matches the tag of a file. Weak tags never match a strong comparison
+fn tag_list_matches(value: &str, etag: &str, strong: bool) -> bool {
+    let value = value.trim();
+    if value == "*" {
+        return true;
+    }
+    let (etag_weak, etag) = split_weak(etag);
+    value.split(',').map(str::trim).any(|tag| {
+        let (weak, tag) = split_weak(tag);
+        tag == etag && !(strong && (weak || etag_weak))
+    })
+}
+
+/// Function to split the `W/` prefix off an entity tag
+fn split_weak(tag: &str) -> (bool, &str) {
+    match tag.strip_prefix("W/") {
+        Some(tag) => (true, tag),
+        None => (false, tag),
+    }
+}
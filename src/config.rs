@@ -0,0 +1,1213 @@
+/* See LICENSE for license details */
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::compress::{self, Encoding};
+use crate::mime;
+
+/// The largest request body (or all the fields of a form together) that will
+/// be read if the config does not say
+const DEFAULT_MAX_BODY: u64 = 1024 * 1024;
+/// The most bytes of files one form may upload if the config does not say
+const DEFAULT_MAX_UPLOAD: u64 = 16 * 1024 * 1024;
+/// Seconds an idle connection is kept open for if the config does not say
+const DEFAULT_KEEP_ALIVE_TIMEOUT: u64 = 5;
+/// Requests served on one connection if the config does not say
+const DEFAULT_MAX_REQUESTS: usize = 100;
+/// Seconds requests get to finish when shutting down if the config does not
+/// say
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+/// Requests handled at once if the config does not say
+const DEFAULT_WORKERS: usize = 5;
+
+/// How much the server prints about what it is doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Only things that stop the server from working
+    Error,
+    /// Also problems the server can carry on after
+    Warn,
+    /// Also what the server is set up to do
+    Info,
+    /// Also every request and response
+    Debug,
+}
+
+impl LogLevel {
+    /// Returns the name of the level as used in the config file
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+
+    /// Function to get a level from its name. Returns None for names that
+    /// are not known
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        match name.trim().to_lowercase().as_str() {
+            "error" => Some(LogLevel::Error),
+            "warn" | "warning" => Some(LogLevel::Warn),
+            "info" => Some(LogLevel::Info),
+            "debug" => Some(LogLevel::Debug),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A problem with a config file, along with where in the file it is. The
+/// line and column start at 1, and are 0 for problems with the file as a
+/// whole
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    file: String,
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl ConfigError {
+    /// Function to create an error about a config as a whole rather than
+    /// one place in it
+    pub fn new(file: &str, message: &str) -> ConfigError {
+        ConfigError {
+            file: file.to_string(),
+            line: 0,
+            column: 0,
+            message: message.to_string(),
+        }
+    }
+
+    /// Returns the name of the config file
+    pub fn file(&self) -> &str {
+        self.file.as_str()
+    }
+
+    /// Returns the line the problem is on, or 0 if it is not on one line
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Returns the column the problem starts at, or 0 if it is not on one
+    /// line
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    /// Returns what the problem is
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+}
+
+/// Shown as `file:line:column: message`, the way compilers show errors
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.message)
+        } else {
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, self.line, self.column, self.message
+            )
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// The settings of the server, as read from a config file. The file is made
+/// of sections like `[server]`, each holding `key = value` lines, where a
+/// value is a "string", a number, `true` or `false`, or a one line
+/// [array, of, values]. `#` starts a comment. See `config.txt` for every
+/// section and key. Anything that is not set keeps its default
+///
+/// # Examples
+///
+/// ```
+/// let text = "[server]\nworkers = 8\nindex = [\"index.html\"]\n\
+///             [limits]\nmax_body = 4096\n";
+///
+/// let config = server::Config::parse(text, "example.txt").unwrap();
+/// assert_eq!(config.workers, 8);
+/// assert_eq!(config.index, vec!["index.html"]);
+/// assert_eq!(config.max_body, 4096);
+/// ```
+///
+/// Mistakes are reported with where they are
+///
+/// ```
+/// let text = "[limits]\nmax_body = lots\n";
+///
+/// let err = server::Config::parse(text, "example.txt").unwrap_err();
+/// assert_eq!((err.line(), err.column()), (2, 12));
+/// assert_eq!(err.to_string(), "example.txt:2:12: expected a value");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The addresses (`host:port`) to accept connections on
+    pub listen: Vec<String>,
+    /// How many requests are handled at once
+    pub workers: usize,
+    /// The directory files are served from
+    pub root: PathBuf,
+    /// The files sent when a directory is asked for, tried in order
+    pub index: Vec<String>,
+    /// Files that are never served, on top of dotfiles and the defaults
+    pub deny: Vec<String>,
+    /// Directories that get a listing when they have no index file
+    pub autoindex: Vec<String>,
+    /// The page sent with each error status
+    pub error_pages: Vec<(u16, PathBuf)>,
+    /// Seconds an idle connection is kept open for
+    pub keep_alive_timeout: u64,
+    /// Requests served on one connection before it is closed
+    pub max_requests: usize,
+    /// The largest request body that is read
+    pub max_body: u64,
+    /// Where files uploaded in forms are stored
+    pub upload_dir: PathBuf,
+    /// Whether the config is read again whenever its file changes
+    pub watch: bool,
+    /// The most bytes of files one form or PUT may upload
+    pub max_upload: u64,
+    /// Seconds requests get to finish when the server shuts down
+    pub shutdown_timeout: u64,
+    /// Paths files may be published to with PUT and removed from with DELETE
+    pub writable: Vec<String>,
+    /// The (name, password) pairs allowed to publish
+    pub users: Vec<(String, String)>,
+    /// A file in the `mime.types` format to read content types from
+    pub mime_file: Option<PathBuf>,
+    /// Content types of extensions, on top of the built-in ones
+    pub mime_types: Vec<(String, String)>,
+    /// The content type of files with an unknown extension
+    pub default_type: String,
+    /// The codings files are compressed with, best first. Empty turns
+    /// compression off
+    pub compress: Vec<Encoding>,
+    /// Files smaller than this are not compressed
+    pub compress_min_size: u64,
+    /// Whether `name.br` and `name.gz` are sent in place of `name`
+    pub precompressed: bool,
+    /// How much is printed
+    pub log_level: LogLevel,
+    /// Where the address of every client is written, if anywhere
+    pub access_log: Option<PathBuf>,
+    /// The file the config was read from
+    path: Option<PathBuf>,
+    /// The keys changed with `set()`, and what they were set to
+    overrides: Vec<(String, String)>,
+    /// The keys that are not known, with the line they are on, which are
+    /// ignored and warned about
+    unknown: Vec<(usize, String)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![String::from("0.0.0.0:8080")],
+            workers: DEFAULT_WORKERS,
+            root: PathBuf::from("."),
+            index: Vec::new(),
+            deny: Vec::new(),
+            autoindex: Vec::new(),
+            error_pages: Vec::new(),
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+            max_requests: DEFAULT_MAX_REQUESTS,
+            max_body: DEFAULT_MAX_BODY,
+            upload_dir: env::temp_dir(),
+            watch: false,
+            max_upload: DEFAULT_MAX_UPLOAD,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            writable: Vec::new(),
+            users: Vec::new(),
+            mime_file: None,
+            mime_types: Vec::new(),
+            default_type: mime::DEFAULT_TYPE.to_string(),
+            compress: Encoding::ALL.to_vec(),
+            compress_min_size: compress::DEFAULT_MIN_SIZE,
+            precompressed: true,
+            log_level: LogLevel::Info,
+            access_log: Some(PathBuf::from("ips.txt")),
+            path: None,
+            overrides: Vec::new(),
+            unknown: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Function to read and check a config file
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be read, is not in the config format, has
+    /// settings that are not valid or in sections that are not known, or
+    /// names files (like the
+    /// document root or error pages) that cannot be used
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+        let text = fs::read_to_string(path).map_err(|err| {
+            ConfigError::new(&name, &format!("could not be read: {}", err))
+        })?;
+        let mut config = Config::parse(&text, &name)?;
+        config.path = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Function to read and check a config from text. `file` is only used
+    /// in errors
+    ///
+    /// # Errors
+    ///
+    /// As for `load()`, apart from reading the file
+    pub fn parse(text: &str, file: &str) -> Result<Config, ConfigError> {
+        let located = |err: Located| ConfigError {
+            file: file.to_string(),
+            line: err.line,
+            column: err.column,
+            message: err.message,
+        };
+        let mut config = Config::default();
+        for entry in parse_entries(text).map_err(located)? {
+            config.apply(entry).map_err(located)?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the file the config was read from, if it was read from one
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Function to set one key after the config has been read, with the
+    /// value written the way it would be in the file. The key is the section
+    /// and the key joined by a dot. Keys set this way win over the file, and
+    /// stay set when the config is read again with `reload()`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut config = server::Config::default();
+    /// config.set("server.workers", "8").unwrap();
+    /// config.set("logging.level", "\"debug\"").unwrap();
+    /// assert_eq!(config.workers, 8);
+    ///
+    /// let err = config.set("server.workers", "\"eight\"").unwrap_err();
+    /// assert_eq!(err.to_string(), "server.workers:1:1: expected a number");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for `parse()`, with the key in place of the file name
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let located = |err: Located| ConfigError {
+            file: key.to_string(),
+            line: err.line,
+            column: err.column,
+            message: err.message,
+        };
+        let (section, name) =
+            match key.rfind('.') {
+                Some(dot) => (&key[..dot], &key[dot + 1..]),
+                None => return Err(ConfigError::new(
+                    key,
+                    "keys are set along with their section, like server.root",
+                )),
+            };
+        if !SECTIONS.contains(&section) {
+            let message = format!("unknown section [{}]", section);
+            return Err(ConfigError::new(key, &message));
+        }
+        let mut scanner = Scanner::new(value, 1);
+        let parsed = scanner.value().map_err(located)?;
+        scanner.end().map_err(located)?;
+        let unknown = self.unknown.len();
+        self.apply(Entry {
+            section: section.to_string(),
+            key: name.to_string(),
+            key_column: 1,
+            line: 1,
+            value: parsed,
+        })
+        .map_err(located)?;
+        // A file can have keys from other versions, but a key that is set
+        // has to be one that does something
+        if self.unknown.len() > unknown {
+            let (_, message) = self.unknown.remove(unknown);
+            return Err(ConfigError::new(key, &message));
+        }
+        self.overrides.push((key.to_string(), value.to_string()));
+        Ok(())
+    }
+
+    /// Function to read the config again from the file it was read from,
+    /// along with every key that was changed with `set()`
+    ///
+    /// # Errors
+    ///
+    /// If the config was not read from a file, or as for `load()`
+    pub fn reload(&self) -> Result<Config, ConfigError> {
+        let path = self.path().ok_or_else(|| {
+            ConfigError::new("config", "was not read from a file")
+        })?;
+        let mut config = Config::load(path)?;
+        for (key, value) in &self.overrides {
+            config.set(key, value)?;
+        }
+        Ok(config)
+    }
+
+    /// Returns the things in the config that are allowed but are likely to be
+    /// mistakes, like paths that do not exist. The server still starts with
+    /// a config that has warnings
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let text = "[publish]\nwritable = [\"/files\"]\n";
+    /// let config = server::Config::parse(text, "example.txt").unwrap();
+    ///
+    /// let warnings = config.warnings();
+    /// assert!(warnings.contains(&String::from(
+    ///     "paths are writable but there are no users to publish to them"
+    /// )));
+    ///
+    /// let text = "[server]\nworker = 4\n";
+    /// let config = server::Config::parse(text, "example.txt").unwrap();
+    /// assert!(config.warnings().contains(&String::from(
+    ///     "unknown key `worker` in [server] on line 2 is ignored"
+    /// )));
+    /// ```
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings: Vec<String> = self
+            .unknown
+            .iter()
+            .map(|(line, message)| {
+                format!("{} on line {} is ignored", message, line)
+            })
+            .collect();
+        if self.index.is_empty() {
+            warnings.push(String::from(
+                "no index files are set, so only directories with a listing \
+                 can be served",
+            ));
+        }
+        if !self.error_pages.iter().any(|(code, _)| *code == 404) {
+            warnings.push(String::from(
+                "no 404 page is set, so a built-in one is sent",
+            ));
+        }
+        for path in &self.autoindex {
+            if !self.root.join(path).is_dir() {
+                warnings.push(format!(
+                    "the listed directory /{} is not in the root",
+                    path
+                ));
+            }
+        }
+        if !self.upload_dir.is_dir() {
+            if !can_create(&self.upload_dir) {
+                warnings.push(format!(
+                    "the upload directory {} cannot be created, so files in \
+                     forms cannot be stored",
+                    self.upload_dir.display()
+                ));
+            }
+        } else if is_read_only(&self.upload_dir) {
+            warnings.push(format!(
+                "the upload directory {} cannot be written to",
+                self.upload_dir.display()
+            ));
+        }
+        if !self.writable.is_empty() && self.users.is_empty() {
+            warnings.push(String::from(
+                "paths are writable but there are no users to publish to them",
+            ));
+        }
+        if self.writable.is_empty() && !self.users.is_empty() {
+            warnings.push(String::from(
+                "there are users but no paths they can publish to",
+            ));
+        }
+        for path in &self.writable {
+            let dir = self.root.join(path.trim_start_matches('/'));
+            if !dir.exists() {
+                warnings.push(format!(
+                    "the writable path {} is not in the root",
+                    path
+                ));
+            }
+        }
+        if let Some(log) = &self.access_log {
+            if log.exists() && is_read_only(log) {
+                warnings.push(format!(
+                    "the access log {} cannot be written to",
+                    log.display()
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// Function to set one key from the file
+    fn apply(&mut self, entry: Entry) -> Result<(), Located> {
+        let Entry {
+            section,
+            key,
+            key_column,
+            line,
+            value,
+        } = entry;
+        match (section.as_str(), key.as_str()) {
+            ("server", "listen") => {
+                let addresses = value.strings()?;
+                for (i, (address, at)) in addresses.iter().enumerate() {
+                    if !is_address(address) {
+                        return Err(at.error(&format!(
+                            "`{}` is not an address like 0.0.0.0:8080",
+                            address
+                        )));
+                    }
+                    if addresses[..i].iter().any(|(other, _)| other == address)
+                    {
+                        return Err(
+                            at.error(&format!("`{}` is listed twice", address))
+                        );
+                    }
+                }
+                self.listen = without_places(addresses);
+            }
+            ("server", "workers") => {
+                self.workers = value.number()? as usize;
+                if self.workers == 0 {
+                    return Err(value.error("there has to be a worker"));
+                }
+            }
+            ("server", "root") => {
+                let root = PathBuf::from(value.string()?);
+                if !root.is_dir() {
+                    return Err(value.error(&format!(
+                        "{} is not a directory",
+                        root.display()
+                    )));
+                }
+                self.root = root;
+            }
+            ("server", "index") => {
+                self.index = without_places(value.strings()?)
+                    .iter()
+                    .map(|name| name.trim_matches('/').to_string())
+                    .collect();
+            }
+            ("server", "deny") => self.deny = without_places(value.strings()?),
+            ("server", "autoindex") => {
+                self.autoindex = without_places(value.strings()?)
+                    .iter()
+                    .map(|path| path.trim_matches('/').to_string())
+                    .collect();
+            }
+            ("server", "upload_dir") => {
+                self.upload_dir = PathBuf::from(value.string()?)
+            }
+            ("server", "watch") => self.watch = value.boolean()?,
+            ("error_pages", code) => {
+                let code = match code.parse::<u16>() {
+                    Ok(code) if (400..600).contains(&code) => code,
+                    _ => {
+                        return Err(Located::at(
+                            line,
+                            key_column,
+                            "error pages are for status codes 400 to 599",
+                        ))
+                    }
+                };
+                let page = PathBuf::from(value.string()?);
+                if let Err(err) = fs::metadata(&page) {
+                    return Err(value.error(&format!(
+                        "could not read {}: {}",
+                        page.display(),
+                        err
+                    )));
+                }
+                self.error_pages.push((code, page));
+            }
+            ("limits", "keep_alive_timeout") => {
+                self.keep_alive_timeout = value.number()?
+            }
+            ("limits", "max_requests") => {
+                self.max_requests = value.number()? as usize
+            }
+            ("limits", "max_body") => self.max_body = value.number()?,
+            ("limits", "max_upload") => self.max_upload = value.number()?,
+            ("limits", "shutdown_timeout") => {
+                self.shutdown_timeout = value.number()?
+            }
+            ("publish", "writable") => {
+                self.writable = without_places(value.strings()?)
+            }
+            ("publish", "users") => {
+                let mut users = Vec::new();
+                for (user, at) in value.strings()? {
+                    match user.find(':') {
+                        Some(colon) => users.push((
+                            user[..colon].to_string(),
+                            user[colon + 1..].to_string(),
+                        )),
+                        None => {
+                            return Err(
+                                at.error("users are written as name:password")
+                            )
+                        }
+                    }
+                }
+                self.users = users;
+            }
+            ("mime", "file") => {
+                let file = PathBuf::from(value.string()?);
+                if !file.is_file() {
+                    return Err(value
+                        .error(&format!("{} is not a file", file.display())));
+                }
+                self.mime_file = Some(file);
+            }
+            ("mime", "default_type") => self.default_type = value.string()?,
+            ("mime.types", extension) => {
+                let content_type = value.string()?;
+                self.mime_types.push((extension.to_string(), content_type));
+            }
+            ("compression", "encodings") => {
+                let mut encodings = Vec::new();
+                for (name, at) in value.strings()? {
+                    match Encoding::from_name(&name) {
+                        Some(Encoding::Identity) | None => {
+                            return Err(at.error(&format!(
+                                "unknown compression `{}`, use br, gzip or \
+                                 deflate",
+                                name
+                            )))
+                        }
+                        Some(encoding) => encodings.push(encoding),
+                    }
+                }
+                self.compress = encodings;
+            }
+            ("compression", "min_size") => {
+                self.compress_min_size = value.number()?
+            }
+            ("compression", "precompressed") => {
+                self.precompressed = value.boolean()?
+            }
+            ("logging", "level") => {
+                let level = value.string()?;
+                self.log_level =
+                    LogLevel::from_name(&level).ok_or_else(|| {
+                        value.error(&format!(
+                            "unknown level `{}`, use error, warn, info or \
+                             debug",
+                            level
+                        ))
+                    })?;
+            }
+            ("logging", "access_log") => {
+                let log = value.string()?;
+                self.access_log = if log.is_empty() {
+                    None
+                } else {
+                    let log = PathBuf::from(log);
+                    if !directory_of(&log).is_dir() {
+                        return Err(value.error(&format!(
+                            "the directory of {} does not exist",
+                            log.display()
+                        )));
+                    }
+                    Some(log)
+                };
+            }
+            (section, key) => {
+                let message = format!("unknown key `{}` in [{}]", key, section);
+                self.unknown.push((line, message));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The sections a config file can have
+const SECTIONS: &[&str] = &[
+    "server",
+    "error_pages",
+    "limits",
+    "publish",
+    "mime",
+    "mime.types",
+    "compression",
+    "logging",
+];
+
+/// Written in the config format with every key in it, set to the value in
+/// use, so it shows what a config does once defaults and `set()` have been
+/// taken into account. Passwords are hidden
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = |path: &PathBuf| quote(&path.to_string_lossy());
+        writeln!(f, "[server]")?;
+        writeln!(f, "listen = {}", array(&self.listen))?;
+        writeln!(f, "workers = {}", self.workers)?;
+        writeln!(f, "root = {}", path(&self.root))?;
+        writeln!(f, "index = {}", array(&self.index))?;
+        writeln!(f, "deny = {}", array(&self.deny))?;
+        writeln!(f, "autoindex = {}", array(&self.autoindex))?;
+        writeln!(f, "upload_dir = {}", path(&self.upload_dir))?;
+        writeln!(f, "watch = {}", self.watch)?;
+
+        writeln!(f, "\n[error_pages]")?;
+        let mut pages = self.error_pages.clone();
+        pages.sort();
+        for (code, page) in &pages {
+            writeln!(f, "{} = {}", code, path(page))?;
+        }
+
+        writeln!(f, "\n[limits]")?;
+        writeln!(f, "keep_alive_timeout = {}", self.keep_alive_timeout)?;
+        writeln!(f, "max_requests = {}", self.max_requests)?;
+        writeln!(f, "max_body = {}", self.max_body)?;
+        writeln!(f, "max_upload = {}", self.max_upload)?;
+        writeln!(f, "shutdown_timeout = {}", self.shutdown_timeout)?;
+
+        writeln!(f, "\n[publish]")?;
+        writeln!(f, "writable = {}", array(&self.writable))?;
+        let users = self
+            .users
+            .iter()
+            .map(|(name, _)| format!("{}:********", name))
+            .collect::<Vec<String>>();
+        writeln!(f, "users = {}", array(&users))?;
+
+        writeln!(f, "\n[mime]")?;
+        if let Some(file) = &self.mime_file {
+            writeln!(f, "file = {}", path(file))?;
+        }
+        writeln!(f, "default_type = {}", quote(&self.default_type))?;
+
+        writeln!(f, "\n[mime.types]")?;
+        for (extension, content_type) in &self.mime_types {
+            writeln!(f, "{} = {}", extension, quote(content_type))?;
+        }
+
+        writeln!(f, "\n[compression]")?;
+        let encodings = self
+            .compress
+            .iter()
+            .map(|encoding| encoding.as_str().to_string())
+            .collect::<Vec<String>>();
+        writeln!(f, "encodings = {}", array(&encodings))?;
+        writeln!(f, "min_size = {}", self.compress_min_size)?;
+        writeln!(f, "precompressed = {}", self.precompressed)?;
+
+        writeln!(f, "\n[logging]")?;
+        writeln!(f, "level = {}", quote(self.log_level.as_str()))?;
+        let log = self
+            .access_log
+            .as_ref()
+            .map_or(String::new(), |log| log.to_string_lossy().to_string());
+        writeln!(f, "access_log = {}", quote(&log))
+    }
+}
+
+/// Function to write a string the way it is written in a config
+fn quote(string: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Function to write a list of strings the way it is written in a config
+fn array(strings: &[String]) -> String {
+    let strings = strings.iter().map(|string| quote(string));
+    format!("[{}]", strings.collect::<Vec<String>>().join(", "))
+}
+
+/// Returns the directory a file is in
+fn directory_of(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Returns whether a file or directory cannot be written to
+fn is_read_only(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly())
+}
+
+/// Function to check whether a missing directory can be made when it is
+/// first needed, which takes its closest existing ancestor to be a directory
+/// that can be written to
+fn can_create(path: &Path) -> bool {
+    if path.exists() {
+        return false;
+    }
+    path.ancestors()
+        .skip(1)
+        .map(|dir| {
+            if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }
+        })
+        .find(|dir| dir.exists())
+        .is_some_and(|dir| dir.is_dir() && !is_read_only(dir))
+}
+
+/// Function to check that an address is a host followed by a port
+fn is_address(address: &str) -> bool {
+    match address.rfind(':') {
+        Some(colon) => colon > 0 && address[colon + 1..].parse::<u16>().is_ok(),
+        None => false,
+    }
+}
+
+/// Function to drop the places strings were found at
+fn without_places(strings: Vec<(String, Value)>) -> Vec<String> {
+    strings.into_iter().map(|(string, _)| string).collect()
+}
+
+/// A problem at a place in the text of a config
+#[derive(Debug)]
+struct Located {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl Located {
+    fn at(line: usize, column: usize, message: &str) -> Located {
+        Located {
+            line,
+            column,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// One `key = value` line, along with the section it is in
+struct Entry {
+    section: String,
+    key: String,
+    key_column: usize,
+    line: usize,
+    value: Value,
+}
+
+/// A value in a config, along with where it starts
+#[derive(Clone, Debug)]
+struct Value {
+    kind: Kind,
+    line: usize,
+    column: usize,
+}
+
+/// The kinds of values
+#[derive(Clone, Debug)]
+enum Kind {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// Function to make an error about the value
+    fn error(&self, message: &str) -> Located {
+        Located::at(self.line, self.column, message)
+    }
+
+    fn string(&self) -> Result<String, Located> {
+        match &self.kind {
+            Kind::String(string) => Ok(string.clone()),
+            _ => Err(self.error("expected a string")),
+        }
+    }
+
+    /// Function to get a list of strings, along with where each of them is.
+    /// A single string is taken as a list of one
+    fn strings(&self) -> Result<Vec<(String, Value)>, Located> {
+        match &self.kind {
+            Kind::String(string) => Ok(vec![(string.clone(), self.clone())]),
+            Kind::Array(values) => values
+                .iter()
+                .map(|value| Ok((value.string()?, value.clone())))
+                .collect(),
+            _ => Err(self.error("expected a string or an array of strings")),
+        }
+    }
+
+    /// Function to get a number that cannot be negative
+    fn number(&self) -> Result<u64, Located> {
+        match self.kind {
+            Kind::Integer(number) if number >= 0 => Ok(number as u64),
+            Kind::Integer(_) => Err(self.error("expected a positive number")),
+            _ => Err(self.error("expected a number")),
+        }
+    }
+
+    fn boolean(&self) -> Result<bool, Located> {
+        match self.kind {
+            Kind::Boolean(boolean) => Ok(boolean),
+            _ => Err(self.error("expected true or false")),
+        }
+    }
+}
+
+/// Function to read every `key = value` line of a config, checking that
+/// nothing is set twice
+fn parse_entries(text: &str) -> Result<Vec<Entry>, Located> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut sections: Vec<String> = Vec::new();
+    let mut section: Option<String> = None;
+    for (number, line) in text.lines().enumerate() {
+        let mut scanner = Scanner::new(line, number + 1);
+        scanner.skip_space();
+        match scanner.peek() {
+            None | Some('#') => continue,
+            Some('[') => {
+                let column = scanner.column();
+                let name = scanner.section()?;
+                if !SECTIONS.contains(&name.as_str()) {
+                    return Err(Located::at(
+                        number + 1,
+                        column,
+                        &format!("unknown section [{}]", name),
+                    ));
+                }
+                if sections.contains(&name) {
+                    return Err(Located::at(
+                        number + 1,
+                        column,
+                        &format!("section [{}] is written twice", name),
+                    ));
+                }
+                sections.push(name.clone());
+                section = Some(name);
+            }
+            Some(_) => {
+                let key_column = scanner.column();
+                let key = scanner.key()?;
+                let section = match &section {
+                    Some(section) => section.clone(),
+                    None => {
+                        return Err(Located::at(
+                            number + 1,
+                            key_column,
+                            "keys have to be in a section, like [server]",
+                        ))
+                    }
+                };
+                scanner.skip_space();
+                if !scanner.eat('=') {
+                    let message = if scanner.peek() == Some(':') {
+                        "expected `=` (the old `key: value` format is no \
+                         longer read)"
+                    } else {
+                        "expected `=` after the key"
+                    };
+                    return Err(scanner.error(message));
+                }
+                scanner.skip_space();
+                let value = scanner.value()?;
+                scanner.end()?;
+                let twice = entries
+                    .iter()
+                    .any(|entry| entry.section == section && entry.key == key);
+                if twice {
+                    return Err(Located::at(
+                        number + 1,
+                        key_column,
+                        &format!("`{}` is set twice in [{}]", key, section),
+                    ));
+                }
+                entries.push(Entry {
+                    section,
+                    key,
+                    key_column,
+                    line: number + 1,
+                    value,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads the parts of one line of a config
+struct Scanner {
+    chars: Vec<char>,
+    position: usize,
+    line: usize,
+}
+
+impl Scanner {
+    fn new(text: &str, line: usize) -> Scanner {
+        Scanner {
+            chars: text.chars().collect(),
+            position: 0,
+            line,
+        }
+    }
+
+    /// Returns the column of the next character
+    fn column(&self) -> usize {
+        self.position + 1
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        Some(c)
+    }
+
+    /// Function to move past a character if it is the next one
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.position += 1;
+        }
+    }
+
+    /// Function to make an error at the next character
+    fn error(&self, message: &str) -> Located {
+        Located::at(self.line, self.column(), message)
+    }
+
+    /// Function to check that only a comment is left on the line
+    fn end(&mut self) -> Result<(), Located> {
+        self.skip_space();
+        match self.peek() {
+            None | Some('#') => Ok(()),
+            Some(_) => Err(self.error("expected the end of the line")),
+        }
+    }
+
+    /// Function to read a bare word, like a key or section name
+    fn word(&mut self, dots: bool) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric()
+                || c == '_'
+                || c == '-'
+                || (dots && c == '.')
+            {
+                word.push(c);
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        word
+    }
+
+    /// Function to read a `[section]` line
+    fn section(&mut self) -> Result<String, Located> {
+        self.eat('[');
+        self.skip_space();
+        let name = self.word(true);
+        if name.is_empty() {
+            return Err(self.error("expected the name of a section"));
+        }
+        self.skip_space();
+        if !self.eat(']') {
+            return Err(self.error("expected `]`"));
+        }
+        self.end()?;
+        Ok(name)
+    }
+
+    /// Function to read a key, which is a bare word or a quoted string
+    fn key(&mut self) -> Result<String, Located> {
+        if self.peek() == Some('"') {
+            return self.basic_string();
+        }
+        let key = self.word(false);
+        if key.is_empty() {
+            return Err(self.error("expected a key"));
+        }
+        Ok(key)
+    }
+
+    /// Function to read a value
+    fn value(&mut self) -> Result<Value, Located> {
+        let (line, column) = (self.line, self.column());
+        let kind = match self.peek() {
+            Some('"') => Kind::String(self.basic_string()?),
+            Some('\'') => Kind::String(self.literal_string()?),
+            Some('[') => Kind::Array(self.array()?),
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '+' => {
+                self.integer()?
+            }
+            Some(c) if c.is_ascii_alphabetic() => match self
+                .word(false)
+                .as_str()
+            {
+                "true" => Kind::Boolean(true),
+                "false" => Kind::Boolean(false),
+                _ => return Err(Located::at(line, column, "expected a value")),
+            },
+            _ => return Err(self.error("expected a value")),
+        };
+        Ok(Value { kind, line, column })
+    }
+
+    /// Function to read a "string", in which `\` starts an escape
+    fn basic_string(&mut self) -> Result<String, Located> {
+        let start = self.column();
+        self.eat('"');
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                None => {
+                    return Err(Located::at(
+                        self.line,
+                        start,
+                        "the string is never closed",
+                    ))
+                }
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let escape = self.column();
+                    let c = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => self.unicode_escape(escape)?,
+                        _ => {
+                            return Err(Located::at(
+                                self.line,
+                                escape,
+                                "unknown escape",
+                            ))
+                        }
+                    };
+                    string.push(c);
+                }
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// Function to read the four hex digits of a `\u` escape
+    fn unicode_escape(&mut self, escape: usize) -> Result<char, Located> {
+        let digits: String = (0..4).filter_map(|_| self.bump()).collect();
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| {
+                Located::at(self.line, escape, "invalid unicode escape")
+            })
+    }
+
+    /// Function to read a 'string', which is taken exactly as written
+    fn literal_string(&mut self) -> Result<String, Located> {
+        let start = self.column();
+        self.eat('\'');
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                None => {
+                    return Err(Located::at(
+                        self.line,
+                        start,
+                        "the string is never closed",
+                    ))
+                }
+                Some('\'') => return Ok(string),
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    /// Function to read an [array, of, values], which has to fit on one line
+    fn array(&mut self) -> Result<Vec<Value>, Located> {
+        let start = self.column();
+        self.eat('[');
+        let mut values = Vec::new();
+        loop {
+            self.skip_space();
+            if self.eat(']') {
+                return Ok(values);
+            }
+            if self.peek().is_none() {
+                return Err(Located::at(
+                    self.line,
+                    start,
+                    "the array is never closed (arrays have to be on one \
+                     line)",
+                ));
+            }
+            values.push(self.value()?);
+            self.skip_space();
+            if !self.eat(',') && self.peek() != Some(']') {
+                return Err(self.error("expected `,` or `]`"));
+            }
+        }
+    }
+
+    /// Function to read a whole number, which may have `_` between digits
+    fn integer(&mut self) -> Result<Kind, Located> {
+        let start = self.column();
+        let mut digits = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+' {
+                if c != '_' {
+                    digits.push(c);
+                }
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        digits
+            .parse()
+            .map(Kind::Integer)
+            .map_err(|_| Located::at(self.line, start, "invalid number"))
+    }
+}
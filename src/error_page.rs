@@ -0,0 +1,114 @@
+/* See LICENSE for license details */
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use crate::autoindex::escape_html;
+use crate::response::StatusCode;
+
+/// The page sent for statuses that have not been given one of their own
+const DEFAULT_TEMPLATE: &str = "<!DOCTYPE html><html><head>\
+     <meta charset=\"utf-8\"><title>{{status}}</title></head><body>\
+     <h1>{{status}}</h1><p>{{message}}</p><hr>\
+     <p><small>Request ID: {{request_id}}</small></p></body></html>";
+
+/// The pages sent along with error statuses. Pages are templates, in which
+/// `{{status}}` (e.g. `404 Not Found`), `{{code}}`, `{{reason}}`, `{{path}}`,
+/// `{{request_id}}` and `{{message}}` (a sentence explaining the status) are
+/// replaced with the details of the response. Statuses without a page of
+/// their own get a simple built-in one
+#[derive(Clone, Debug, Default)]
+pub struct ErrorPages {
+    pages: HashMap<u16, String>,
+}
+
+impl ErrorPages {
+    /// Function to create a set of error pages that only has the built-in
+    /// page
+    pub fn new() -> Self {
+        ErrorPages::default()
+    }
+
+    /// Function to set the template for a status code
+    pub fn insert(&mut self, code: u16, template: &str) {
+        self.pages.insert(code, template.to_string());
+    }
+
+    /// Function to read the template for a status code from a file
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be read
+    pub fn load(&mut self, code: u16, filename: &str) -> io::Result<()> {
+        let template = fs::read_to_string(filename)?;
+        self.pages.insert(code, template);
+        Ok(())
+    }
+
+    /// Returns if a status code has a page of its own
+    pub fn contains(&self, code: u16) -> bool {
+        self.pages.contains_key(&code)
+    }
+
+    /// Function to make the page for a status. Everything filled into the
+    /// page is html escaped
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use server::StatusCode;
+    ///
+    /// let mut pages = server::ErrorPages::new();
+    /// pages.insert(404, "<p>{{path}} is gone ({{code}}, {{request_id}})</p>");
+    ///
+    /// let page = pages.render(StatusCode::NotFound, "/<old>", "00af");
+    /// assert_eq!(page, "<p>/&lt;old&gt; is gone (404, 00af)</p>");
+    ///
+    /// let page = pages.render(StatusCode::Forbidden, "/secret", "00b0");
+    /// assert!(page.contains("<h1>403 Forbidden</h1>"));
+    /// ```
+    pub fn render(
+        &self,
+        status: StatusCode,
+        path: &str,
+        request_id: &str,
+    ) -> String {
+        let template = self
+            .pages
+            .get(&status.code())
+            .map_or(DEFAULT_TEMPLATE, String::as_str);
+        template
+            .replace("{{status}}", &escape_html(&status.to_string()))
+            .replace("{{code}}", &status.code().to_string())
+            .replace("{{reason}}", &escape_html(status.reason()))
+            .replace("{{message}}", message(status.code()))
+            .replace("{{request_id}}", &escape_html(request_id))
+            .replace("{{path}}", &escape_html(path))
+    }
+}
+
+/// Function to explain what a status means to whoever sees the page
+fn message(code: u16) -> &'static str {
+    match code {
+        400 => "The request could not be understood.",
+        401 => "You have to log in to do this.",
+        403 => "You do not have permission to access this.",
+        404 => "The requested page was not found on this server.",
+        405 => "This method is not allowed here.",
+        408 => "The request took too long to arrive.",
+        409 => "The request conflicts with what is already there.",
+        411 => "The request has to say how long its body is.",
+        412 => "A condition of the request does not hold.",
+        413 => "The request is larger than allowed.",
+        414 => "The requested address is too long.",
+        415 => "The request body is in a format that is not supported.",
+        416 => "The requested range is not part of the file.",
+        417 => "The expectation of the request cannot be met.",
+        431 => "The request headers are too large.",
+        500 => "Something went wrong on the server.",
+        501 => "The server does not support this.",
+        503 => "The server cannot handle the request right now.",
+        505 => "This version of http is not supported.",
+        _ => "",
+    }
+}
@@ -0,0 +1,668 @@
+/* See LICENSE for license details */
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::headers::Headers;
+use crate::request::Request;
+use crate::response::StatusCode;
+use crate::sandbox::percent_decode;
+
+/// The most bytes the headers of a single multipart part may take up
+const MAX_PART_HEADERS: usize = 8 * 1024;
+/// How much of a multipart body is read from the connection at a time
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Used to keep the names of uploaded files unique within the process
+static UPLOAD_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Everything that can go wrong while reading a form
+#[derive(Debug)]
+pub enum FormError {
+    /// The body is not a form the server knows how to read
+    UnsupportedType(String),
+    /// The body does not match its content type
+    Malformed(String),
+    /// The form fields or uploaded files are larger than allowed
+    TooLarge,
+    /// An uploaded file could not be stored
+    Storage(io::Error),
+    /// The connection could not be read from
+    Io(io::Error),
+    /// A field asked for with `Form::get_as()` is not in the form
+    MissingField(String),
+    /// A field asked for with `Form::get_as()` is not of the asked type
+    InvalidField(String),
+}
+
+impl FormError {
+    /// Returns the status to respond with, or None if the connection
+    /// should just be dropped
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            FormError::UnsupportedType(_) => {
+                Some(StatusCode::UnsupportedMediaType)
+            }
+            FormError::Malformed(_)
+            | FormError::MissingField(_)
+            | FormError::InvalidField(_) => Some(StatusCode::BadRequest),
+            FormError::TooLarge => Some(StatusCode::PayloadTooLarge),
+            FormError::Storage(_) => Some(StatusCode::InternalServerError),
+            FormError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormError::UnsupportedType(content_type) => {
+                write!(f, "unsupported form type: {}", content_type)
+            }
+            FormError::Malformed(reason) => {
+                write!(f, "malformed form: {}", reason)
+            }
+            FormError::TooLarge => write!(f, "form too large"),
+            FormError::Storage(err) => {
+                write!(f, "could not store upload: {}", err)
+            }
+            FormError::Io(err) => write!(f, "{}", err),
+            FormError::MissingField(name) => {
+                write!(f, "missing form field: {}", name)
+            }
+            FormError::InvalidField(name) => {
+                write!(f, "invalid form field: {}", name)
+            }
+        }
+    }
+}
+
+impl Error for FormError {}
+
+impl From<io::Error> for FormError {
+    /// Errors while reading the body. Bodies that are cut short or badly
+    /// chunked are the client's fault, anything else is the connection's
+    fn from(err: io::Error) -> FormError {
+        match err.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                FormError::Malformed(err.to_string())
+            }
+            _ => FormError::Io(err),
+        }
+    }
+}
+
+/// How big a form may be and where uploaded files go
+#[derive(Clone, Debug)]
+pub struct FormLimits {
+    upload_dir: PathBuf,
+    max_fields: u64,
+    max_uploads: u64,
+}
+
+impl FormLimits {
+    /// Function to create the limits for reading a form. `max_fields` is the
+    /// most bytes all the plain fields together may take up, `max_uploads`
+    /// the most bytes all the uploaded files together may take up. Uploaded
+    /// files are written to `upload_dir`, which is created if needed
+    pub fn new<P: AsRef<Path>>(
+        upload_dir: P,
+        max_fields: u64,
+        max_uploads: u64,
+    ) -> FormLimits {
+        FormLimits {
+            upload_dir: upload_dir.as_ref().to_path_buf(),
+            max_fields,
+            max_uploads,
+        }
+    }
+
+    /// Returns the directory uploaded files are written to
+    pub fn upload_dir(&self) -> &Path {
+        self.upload_dir.as_path()
+    }
+}
+
+/// A file sent as part of a multipart form. The contents are stored in the
+/// upload directory under a name picked by the server, never the one sent by
+/// the client
+#[derive(Clone, Debug)]
+pub struct UploadedFile {
+    field: String,
+    filename: String,
+    content_type: String,
+    path: PathBuf,
+    size: u64,
+}
+
+impl UploadedFile {
+    /// Returns the name of the form field the file was sent in
+    pub fn field(&self) -> &str {
+        self.field.as_str()
+    }
+
+    /// Returns the file name the client sent, without any directories
+    pub fn filename(&self) -> &str {
+        self.filename.as_str()
+    }
+
+    /// Returns the content type the client sent for the file
+    pub fn content_type(&self) -> &str {
+        self.content_type.as_str()
+    }
+
+    /// Returns where the contents of the file were stored
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Returns the size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// The fields and files of a form sent in the body of a request
+#[derive(Clone, Debug, Default)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+}
+
+impl Form {
+    /// Function to read the form in the body of a request. The body is read
+    /// from `reader`, which must be where the head of the request was read
+    /// from. Both `application/x-www-form-urlencoded` and
+    /// `multipart/form-data` bodies are understood, with the files of the
+    /// latter written straight to the upload directory as they arrive
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use server::{Form, FormLimits, Request};
+    ///
+    /// let mut raw = "POST / HTTP/1.1\r\nHost: localhost\r\n\
+    ///                Content-Type: multipart/form-data; boundary=XyZ\r\n\
+    ///                Content-Length: 66\r\n\r\n\
+    ///                --XyZ\r\n\
+    ///                Content-Disposition: form-data; name=\"age\"\r\n\r\n\
+    ///                42\r\n\
+    ///                --XyZ--\r\n".as_bytes();
+    /// let request = Request::read_head(&mut raw).unwrap();
+    /// let limits = FormLimits::new(std::env::temp_dir(), 1024, 1024);
+    ///
+    /// let form = Form::read_from(&request, &mut raw, &limits).unwrap();
+    /// assert_eq!(form.get_as::<u32>("age").unwrap(), 42);
+    /// ```
+    pub fn read_from<R: BufRead>(
+        request: &Request,
+        reader: &mut R,
+        limits: &FormLimits,
+    ) -> Result<Form, FormError> {
+        Form::read_body(request, &mut request.body_reader(reader), limits)
+    }
+
+    /// Function to read the form in the body of a request that has already
+    /// been read, like the ones routes are given. Files in it are written to
+    /// the upload directory, where they are left for the caller to move or
+    /// remove (see `remove_files()`)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use server::{Form, FormLimits, Request};
+    ///
+    /// let mut raw = "POST /api/users HTTP/1.1\r\nHost: localhost\r\n\
+    ///                Content-Type: application/x-www-form-urlencoded\r\n\
+    ///                Content-Length: 15\r\n\r\n\
+    ///                name=Ann&age=42".as_bytes();
+    /// let request = Request::read_from(&mut raw, 1024).unwrap();
+    /// let limits = FormLimits::new(std::env::temp_dir(), 1024, 1024);
+    ///
+    /// let form = Form::from_request(&request, &limits).unwrap();
+    /// assert_eq!(form.get("name"), Some("Ann"));
+    /// assert_eq!(form.get_as::<u32>("age").unwrap(), 42);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// As for `read_from()`
+    pub fn from_request(
+        request: &Request,
+        limits: &FormLimits,
+    ) -> Result<Form, FormError> {
+        Form::read_body(request, &mut request.body(), limits)
+    }
+
+    /// Function to read a form from `body`, which is the body of `request`
+    /// without any framing
+    fn read_body<B: Read>(
+        request: &Request,
+        body: &mut B,
+        limits: &FormLimits,
+    ) -> Result<Form, FormError> {
+        let content_type = request.header("Content-Type").unwrap_or("");
+        let (media_type, params) = split_params(content_type);
+        match media_type.as_str() {
+            "application/x-www-form-urlencoded" => {
+                let mut raw = Vec::new();
+                body.by_ref()
+                    .take(limits.max_fields.saturating_add(1))
+                    .read_to_end(&mut raw)?;
+                if raw.len() as u64 > limits.max_fields {
+                    return Err(FormError::TooLarge);
+                }
+                Form::parse_urlencoded(&raw)
+            }
+            "multipart/form-data" => {
+                let boundary = params
+                    .iter()
+                    .find(|(name, _)| name == "boundary")
+                    .map(|(_, value)| value.as_str())
+                    .filter(|boundary| !boundary.is_empty())
+                    .ok_or_else(|| malformed("missing boundary"))?;
+                let mut multipart = Multipart::new(body, boundary, limits);
+                let form = multipart.read_form()?;
+                // Anything after the last boundary is ignored, but still has
+                // to be read so the next request on the connection is found
+                if let Err(err) = io::copy(body, &mut io::sink()) {
+                    form.remove_files();
+                    return Err(err.into());
+                }
+                Ok(form)
+            }
+            _ => Err(FormError::UnsupportedType(content_type.to_string())),
+        }
+    }
+
+    /// Function to parse an `application/x-www-form-urlencoded` body (or
+    /// query string)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let form = server::Form::parse_urlencoded(b"name=J%C3%B6rg+M&x=1&x=2")
+    ///     .unwrap();
+    ///
+    /// assert_eq!(form.get("name"), Some("Jörg M"));
+    /// assert_eq!(form.get_all("x"), vec!["1", "2"]);
+    /// ```
+    pub fn parse_urlencoded(body: &[u8]) -> Result<Form, FormError> {
+        let body = String::from_utf8_lossy(body);
+        let mut form = Form::default();
+        for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+            let mut pair = pair.splitn(2, '=');
+            let name = decode_component(pair.next().unwrap_or(""))?;
+            let value = decode_component(pair.next().unwrap_or(""))?;
+            form.fields.push((name, value));
+        }
+        Ok(form)
+    }
+
+    /// Returns the value of the first field with the given name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the values of every field with the given name
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Returns the value of the first field with the given name parsed into
+    /// any type that implements `FromStr`
+    pub fn get_as<T: FromStr>(&self, name: &str) -> Result<T, FormError> {
+        self.get(name)
+            .ok_or_else(|| FormError::MissingField(name.to_string()))?
+            .trim()
+            .parse()
+            .map_err(|_| FormError::InvalidField(name.to_string()))
+    }
+
+    /// Returns an iterator over the (name, value) pairs of the plain fields
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Returns the first file sent in the field with the given name
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.field == name)
+    }
+
+    /// Returns every uploaded file
+    pub fn files(&self) -> &[UploadedFile] {
+        self.files.as_slice()
+    }
+
+    /// Function to delete the files uploaded with the form, once they are no
+    /// longer needed
+    pub fn remove_files(&self) {
+        for file in &self.files {
+            fs::remove_file(&file.path).ok();
+        }
+    }
+}
+
+/// A reader for a `multipart/form-data` body, which has to be scanned for
+/// the boundary between parts as it is read
+struct Multipart<'a, R> {
+    body: &'a mut R,
+    limits: &'a FormLimits,
+    buf: Vec<u8>,
+    delimiter: Vec<u8>,
+    field_bytes: u64,
+    upload_bytes: u64,
+}
+
+impl<'a, R: Read> Multipart<'a, R> {
+    fn new(
+        body: &'a mut R,
+        boundary: &str,
+        limits: &'a FormLimits,
+    ) -> Multipart<'a, R> {
+        Multipart {
+            body,
+            limits,
+            // The first boundary does not need a line break before it, so
+            // pretend there was one
+            buf: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{}", boundary).into_bytes(),
+            field_bytes: 0,
+            upload_bytes: 0,
+        }
+    }
+
+    /// Reads every part of the body up to the closing boundary. If any part
+    /// cannot be read, the files of the parts before it are deleted again
+    fn read_form(&mut self) -> Result<Form, FormError> {
+        let mut form = Form::default();
+        match self.read_parts(&mut form) {
+            Ok(()) => Ok(form),
+            Err(err) => {
+                form.remove_files();
+                Err(err)
+            }
+        }
+    }
+
+    /// Reads the parts of the body into `form`
+    fn read_parts(&mut self, form: &mut Form) -> Result<(), FormError> {
+        // Anything before the first boundary is ignored
+        self.read_part(&mut |_| Ok(()))?;
+        while !self.is_last()? {
+            let headers = self.read_headers()?;
+            let disposition = headers.get("Content-Disposition").unwrap_or("");
+            let (kind, params) = split_params(disposition);
+            if kind != "form-data" {
+                return Err(malformed("part is not form data"));
+            }
+            let param = |key: &str| {
+                params
+                    .iter()
+                    .find(|(name, _)| name == key)
+                    .map(|(_, value)| value.clone())
+            };
+            let name = param("name")
+                .ok_or_else(|| malformed("part without a name"))?;
+            match param("filename") {
+                Some(filename) => {
+                    let content_type = headers
+                        .get("Content-Type")
+                        .unwrap_or("application/octet-stream");
+                    let file = self.read_file(name, &filename, content_type)?;
+                    form.files.push(file);
+                }
+                None => {
+                    let value = self.read_field()?;
+                    form.fields.push((name, value));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads more of the body into the buffer. Returns false if the body has
+    /// ended
+    fn fill(&mut self) -> Result<bool, FormError> {
+        let start = self.buf.len();
+        self.buf.resize(start + CHUNK_SIZE, 0);
+        match self.body.read(&mut self.buf[start..]) {
+            Ok(read) => {
+                self.buf.truncate(start + read);
+                Ok(read > 0)
+            }
+            Err(err) => {
+                self.buf.truncate(start);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Passes everything up to the next boundary to `sink` and consumes the
+    /// boundary
+    fn read_part(
+        &mut self,
+        sink: &mut dyn FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<(), FormError> {
+        loop {
+            if let Some(start) = find(&self.buf, &self.delimiter) {
+                sink(&self.buf[..start])?;
+                self.buf.drain(..start + self.delimiter.len());
+                return Ok(());
+            }
+            // Hold back enough to catch a boundary split between two reads
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let done = self.buf.len() - keep;
+                sink(&self.buf[..done])?;
+                self.buf.drain(..done);
+            }
+            if !self.fill()? {
+                return Err(malformed("missing closing boundary"));
+            }
+        }
+    }
+
+    /// Checks what follows a boundary. `--` means it was the closing
+    /// boundary, otherwise the line break before the next part is consumed
+    fn is_last(&mut self) -> Result<bool, FormError> {
+        let line = self.read_line()?;
+        if line.starts_with(b"--") {
+            return Ok(true);
+        }
+        // Whitespace is allowed after a boundary
+        if line.iter().any(|&byte| byte != b' ' && byte != b'\t') {
+            return Err(malformed("garbage after boundary"));
+        }
+        Ok(false)
+    }
+
+    /// Reads a line ending in `\r\n` out of the body
+    fn read_line(&mut self) -> Result<Vec<u8>, FormError> {
+        loop {
+            if let Some(end) = find(&self.buf, b"\r\n") {
+                let line = self.buf[..end].to_vec();
+                self.buf.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buf.len() > MAX_PART_HEADERS {
+                return Err(malformed("line too long"));
+            }
+            if !self.fill()? {
+                // The closing boundary may be the very end of the body
+                if self.buf.starts_with(b"--") {
+                    return Ok(self.buf.split_off(0));
+                }
+                return Err(malformed("body ended early"));
+            }
+        }
+    }
+
+    /// Reads the headers at the start of a part
+    fn read_headers(&mut self) -> Result<Headers, FormError> {
+        let mut headers = Headers::new();
+        let mut size = 0;
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(headers);
+            }
+            size += line.len();
+            if size > MAX_PART_HEADERS {
+                return Err(malformed("part headers too large"));
+            }
+            let line = String::from_utf8_lossy(&line);
+            let mut header = line.splitn(2, ':');
+            let name = header.next().unwrap_or("").trim();
+            let value = header
+                .next()
+                .ok_or_else(|| malformed("part header without a colon"))?;
+            headers.append(name, value.trim());
+        }
+    }
+
+    /// Reads the value of a plain field
+    fn read_field(&mut self) -> Result<String, FormError> {
+        let mut value = Vec::new();
+        let max = self.limits.max_fields;
+        let mut total = self.field_bytes;
+        self.read_part(&mut |bytes| {
+            total += bytes.len() as u64;
+            if total > max {
+                return Err(FormError::TooLarge);
+            }
+            value.extend_from_slice(bytes);
+            Ok(())
+        })?;
+        self.field_bytes = total;
+        Ok(String::from_utf8_lossy(&value).to_string())
+    }
+
+    /// Streams the contents of a file part into the upload directory
+    fn read_file(
+        &mut self,
+        field: String,
+        filename: &str,
+        content_type: &str,
+    ) -> Result<UploadedFile, FormError> {
+        let dir = self.limits.upload_dir.clone();
+        fs::create_dir_all(&dir).map_err(FormError::Storage)?;
+        let path = dir.join(upload_name());
+        let mut file = File::create(&path).map_err(FormError::Storage)?;
+
+        let max = self.limits.max_uploads;
+        let mut total = self.upload_bytes;
+        let start = total;
+        let result = self.read_part(&mut |bytes| {
+            total += bytes.len() as u64;
+            if total > max {
+                return Err(FormError::TooLarge);
+            }
+            file.write_all(bytes).map_err(FormError::Storage)
+        });
+        if let Err(err) = result {
+            // Half written files are of no use to anyone
+            fs::remove_file(&path).ok();
+            return Err(err);
+        }
+        self.upload_bytes = total;
+
+        // Browsers on some systems send the whole path of the file
+        let filename = filename
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or("")
+            .to_string();
+        Ok(UploadedFile {
+            field,
+            filename,
+            content_type: content_type.to_string(),
+            path,
+            size: total - start,
+        })
+    }
+}
+
+/// Function to make a name for an uploaded file that no other upload has
+pub(crate) fn upload_name() -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "upload-{}-{}-{}-{}",
+        time.as_secs(),
+        time.subsec_nanos(),
+        std::process::id(),
+        UPLOAD_COUNT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Function to split a header value like `text/html; charset="utf-8"` into
+/// its lowercased first part and its parameters. Parameter names are
+/// lowercased and quoted values are unquoted
+pub(crate) fn split_params(value: &str) -> (String, Vec<(String, String)>) {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in value.chars() {
+        if escaped {
+            current.push(c);
+            escaped = false;
+        } else if quoted && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c == ';' && !quoted {
+            parts.push(current.split_off(0));
+        } else {
+            current.push(c);
+        }
+    }
+    parts.push(current);
+
+    let first = parts[0].trim().to_ascii_lowercase();
+    let params = parts[1..]
+        .iter()
+        .filter_map(|param| {
+            let mut param = param.splitn(2, '=');
+            let name = param.next()?.trim().to_ascii_lowercase();
+            let value = param.next()?.trim().to_string();
+            Some((name, value))
+        })
+        .collect();
+    (first, params)
+}
+
+/// Function to decode one name or value of a urlencoded form
+fn decode_component(component: &str) -> Result<String, FormError> {
+    let component = component.replace('+', " ");
+    let decoded = percent_decode(&component)
+        .ok_or_else(|| malformed("broken percent escape"))?;
+    Ok(String::from_utf8_lossy(&decoded).to_string())
+}
+
+/// Returns where `needle` first appears in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn malformed(reason: &str) -> FormError {
+    FormError::Malformed(reason.to_string())
+}
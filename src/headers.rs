@@ -0,0 +1,116 @@
+/* See LICENSE for license details */
+use std::fmt;
+
+/// A list of http headers. Header names are compared without caring about
+/// case (as http requires) but keep the case they were added with, and a
+/// header may appear more than once
+#[derive(Clone, Debug, Default)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Function to create an empty list of headers
+    pub fn new() -> Headers {
+        Headers {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the first header with the given name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut headers = server::Headers::new();
+    /// headers.append("Content-Type", "text/html");
+    ///
+    /// assert_eq!(headers.get("content-type"), Some("text/html"));
+    /// assert_eq!(headers.get("Host"), None);
+    /// ```
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the values of every header with the given name in the order
+    /// they were added
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    /// Returns if there is at least one header with the given name
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Checks if any header with the given name contains `token` in its comma
+    /// separated list of values, e.g. `close` in `Connection: close`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut headers = server::Headers::new();
+    /// headers.append("Connection", "keep-alive, Upgrade");
+    ///
+    /// assert!(headers.has_token("connection", "upgrade"));
+    /// assert!(!headers.has_token("connection", "close"));
+    /// ```
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name).iter().any(|value| {
+            value
+                .split(',')
+                .any(|part| part.trim().eq_ignore_ascii_case(token))
+        })
+    }
+
+    /// Adds a header, keeping any other headers with the same name
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    /// Sets a header, replacing every other header with the same name
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Removes every header with the given name
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// Returns an iterator over the (name, value) pairs of the headers
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// Returns the number of headers, counting repeated names separately
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns if there are no headers at all
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for Headers {
+    /// Writes the headers the way they appear on the wire
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in self.iter() {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        Ok(())
+    }
+}
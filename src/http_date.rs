@@ -0,0 +1,65 @@
+/* See LICENSE for license details */
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+extern crate chrono;
+use chrono::prelude::*;
+
+/// The format of dates in http headers, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+const IMF_FIXDATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Function to format a time the way http headers like Last-Modified want
+/// it. Anything below a second is dropped
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(784111777);
+/// assert_eq!(server::format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+/// ```
+pub fn format_http_date(time: SystemTime) -> String {
+    let time: DateTime<Utc> = DateTime::from(time);
+    time.format(IMF_FIXDATE).to_string()
+}
+
+/// Function to parse a date from a http header. Besides the usual format the
+/// two obsolete ones clients are still allowed to send are understood
+///
+/// # Examples
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let time = UNIX_EPOCH + Duration::from_secs(784111777);
+/// for date in &[
+///     "Sun, 06 Nov 1994 08:49:37 GMT",
+///     "Sunday, 06-Nov-94 08:49:37 GMT",
+///     "Sun Nov  6 08:49:37 1994",
+/// ] {
+///     assert_eq!(server::parse_http_date(date), Some(time));
+/// }
+/// assert_eq!(server::parse_http_date("yesterday"), None);
+/// ```
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let date = date.trim();
+    let parsed = NaiveDateTime::parse_from_str(date, IMF_FIXDATE)
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%A, %d-%b-%y %T GMT"))
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%a %b %e %T %Y"))
+        .ok()?;
+    let seconds = Utc.from_utc_datetime(&parsed).timestamp();
+    if seconds < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+/// Function to drop anything below a second from a time, since that is all
+/// http dates can hold
+pub(crate) fn whole_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
@@ -5,53 +5,180 @@
 //! `rust_server` is a project of mine to create a simple, functional
 //! multithreaded server in rust
 
-use std::convert::TryInto;
 use std::env;
-use std::fs::{self, File, OpenOptions};
-use std::io::{prelude::*, BufReader};
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{self, prelude::*, BufReader, IsTerminal, SeekFrom};
 use std::net::{TcpListener, TcpStream};
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 extern crate chrono;
 use chrono::prelude::*;
+extern crate libc;
 
+mod autoindex;
+mod compress;
+mod conditional;
+mod config;
+mod error_page;
+mod form;
+mod headers;
+mod http_date;
+mod middleware;
+mod mime;
+mod poll;
+mod publish;
+mod range;
+mod reload;
+mod request;
+mod response;
+mod router;
+mod sandbox;
+mod shutdown;
+mod signals;
 pub mod thread_pool;
 
+pub use autoindex::{Listing, ListingQuery, SortKey};
+use compress::compress_reader;
+pub use compress::{is_compressible, negotiate_encoding, Encoding};
+pub use conditional::{check_preconditions, file_etag, Precondition};
+pub use config::{Config, ConfigError, LogLevel};
+pub use error_page::ErrorPages;
+pub use form::{Form, FormError, FormLimits, UploadedFile};
+pub use headers::Headers;
+pub use http_date::{format_http_date, parse_http_date};
+pub use middleware::Middleware;
+pub use mime::MimeTypes;
+use poll::Waker;
+pub use publish::base64_decode;
+use publish::{PublishError, Published, Publisher};
+use range::MultipartRanges;
+pub use range::{if_range_matches, Ranges};
+pub use reload::Reloader;
+use reload::{Watcher, WATCH_INTERVAL};
+pub use request::{BodyReader, Method, ParseError, Request, Version};
+pub use response::{Body, Response, StatusCode};
+pub use router::{Params, Route, Router};
+pub use sandbox::{percent_decode, DocumentRoot, SandboxError};
+use shutdown::{Connections, Tracked};
+pub use shutdown::{ServerHandle, ShutdownReport, Stopper};
+
+/// Counts the requests the server has answered, to give each an ID
+static REQUEST_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// How long the server waits before accepting connections again when it
+/// could not accept one, like when it has run out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
 pub struct Server {
     threadpool: thread_pool::ThreadPool,
-    workers: usize,
-    input_thread: Option<thread::JoinHandle<()>>,
+    router: Router,
+    middleware: Vec<Arc<dyn Middleware>>,
+    reloader: Reloader,
+    stopper: Stopper,
+    handles_signals: bool,
+    /// Wakes the server thread up when there is something to do besides
+    /// accepting connections
+    waker: Arc<Waker>,
 }
 
 impl Server {
     /// Creates a new server to use. Requires that the number of workers be
-    /// greater than zero
+    /// greater than zero. When run from a terminal, `exit` and `reload` can
+    /// be typed into it to shut the server down or read the config again
     ///
     /// # Examples
     ///
     /// ```
     /// let server = server::Server::new(5);
     ///
-    /// let thread = server.start_at("127.0.0.1:8080", "config.txt");
-    /// // Join the thread later
+    /// let handle = server.start_at("127.0.0.1:0", "config.txt");
+    /// // Join or shut down the server later
     /// ```
     ///
     /// # Panics
     ///
-    /// If the number of workers is less than zero
+    /// If the number of workers is less than zero, or the server has no way
+    /// to be woken up (like when it is out of file descriptors)
     pub fn new(num: usize) -> Server {
         assert!(num > 0);
+        let waker = Arc::new(Waker::new().expect("Could not create a waker"));
         let mut threadpool = thread_pool::ThreadPool::new(num);
-        // Option trick so that we can take the thread later to join it
-        let input_thread = Option::Some(threadpool.input());
+        let reloader = Reloader::new(Arc::clone(&waker));
+        let stopper = Stopper::new(Arc::clone(&waker));
+        // The input thread is never joined, as it may be waiting for input
+        // that never comes when the server is shut down some other way. It
+        // is not started at all when nobody can type into it, like in the
+        // background or under a service manager
+        if io::stdin().is_terminal() {
+            threadpool.input(reloader.clone(), stopper.clone());
+        }
         Server {
             threadpool,
-            workers: num,
-            input_thread,
+            router: Router::new(),
+            middleware: Vec::new(),
+            reloader,
+            stopper,
+            handles_signals: false,
+            waker,
         }
     }
 
+    /// Function to answer requests for a method and path with a handler of
+    /// your own instead of a file. Patterns can have `:name` parameters and
+    /// end in a `*` wildcard (see `Router`). Paths no route matches are still
+    /// served from the document root. Returns the route, so middleware can
+    /// be wrapped around just it
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use server::{Method, Params, Request, Response, StatusCode};
+    ///
+    /// let mut server = server::Server::new(5);
+    /// server.route(
+    ///     Method::Get,
+    ///     "/api/users/:id",
+    ///     |_: &Request, params: &Params| {
+    ///         let id = params.get("id").unwrap_or("");
+    ///         Response::new(StatusCode::Ok)
+    ///             .with_header("Content-Type", "application/json")
+    ///             .with_body(format!("{{\"id\":\"{}\"}}", id))
+    ///     },
+    /// );
+    ///
+    /// let handle = server.start_at("127.0.0.1:0", "config.txt");
+    /// // Join or shut down the server later
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the pattern is not a valid route pattern
+    pub fn route<F>(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        handler: F,
+    ) -> &mut Route
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.router.route(method, pattern, handler)
+    }
+
+    /// Function to wrap middleware around the handling of every request,
+    /// routed or not. Middleware added first runs outermost, and serving
+    /// files is always the innermost part
+    pub fn wrap<M: Middleware + 'static>(
+        &mut self,
+        middleware: M,
+    ) -> &mut Server {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
     /// Executes a job passed to it through the workers the thread pool
     /// maintains. It is usually not needed to call this as `start_at()`
     /// handles this by itself
@@ -64,105 +191,391 @@ impl Server {
 
     /// Returns the current state of the server
     pub fn is_dead(&self) -> bool {
-        return self.threadpool.is_dead();
+        self.threadpool.is_dead()
+    }
+
+    /// Returns a handle to make the server read its config file again once
+    /// it has been started, as typing `reload` into the console does
+    pub fn reloader(&self) -> Reloader {
+        self.reloader.clone()
+    }
+
+    /// Returns a handle to shut the server down gracefully once it has been
+    /// started, as typing `exit` into the console does
+    pub fn stopper(&self) -> Stopper {
+        self.stopper.clone()
+    }
+
+    /// Function to make the server answer signals sent to the process once
+    /// it has been started. SIGTERM and SIGINT shut it down gracefully, and
+    /// a second one exits straight away. SIGHUP reads the config again and
+    /// SIGUSR1 opens the access log again, for after it has been rotated.
+    /// Only works on unix, and only one server in a process should do this
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut server = server::Server::new(5);
+    /// server.handle_signals();
+    ///
+    /// let handle = server.start_at("127.0.0.1:0", "config.txt");
+    /// // `kill -TERM <pid>` now shuts the server down
+    /// ```
+    pub fn handle_signals(&mut self) -> &mut Server {
+        self.handles_signals = true;
+        self
     }
 
     /// Starts the server at a given ip address and with a given config file
-    /// Automatically handles any requests and returns the handle to the
-    /// main server thread
+    /// Automatically handles any requests and returns a handle to wait for
+    /// the server or shut it down. Any addresses in the config file are
+    /// ignored
     ///
     /// # Examples
     ///
     /// ```
     /// let server = server::Server::new(5);
     ///
-    /// let thread = server.start_at("127.0.0.1:8080", "config.txt");
-    /// // Join the thread later
+    /// let handle = server.start_at("127.0.0.1:0", "config.txt");
+    /// // Join or shut down the server later
     /// ```
     ///
     /// # Panics
     ///
-    /// - If the TcpListener could not be set to non-blocking
+    /// - If the config file could not be read or is not valid
+    /// - If the address could not be listened on
     /// - If the ip logging file could not be opened or written to
-    /// - If the thread could not be paused while shutting down (should not
-    ///   happen)
     /// - If the thread could not be created
-    pub fn start_at(
-        self,
-        addr: &str,
-        config: &'static str,
-    ) -> thread::JoinHandle<()> {
-        let listener = TcpListener::bind(addr).unwrap();
-        // The environment variable 'debug' can be set to 1 for useful
-        // debugging purposes
-        let is_debug = env::var("debug").is_ok();
-        listener.set_nonblocking(true).unwrap();
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .append(true)
-            .open("./ips.txt")
-            .unwrap();
-        let parser = Parse::new(config);
+    pub fn start_at(self, addr: &str, config: &'static str) -> ServerHandle {
+        let mut config =
+            Config::load(config).unwrap_or_else(|err| panic!("{}", err));
+        config.listen = vec![addr.to_string()];
+        self.start(config).unwrap()
+    }
+
+    /// Starts the server with a config, listening on every address in it.
+    /// The number of workers is the one the server was created with, so
+    /// create it with `config.workers` to use the one from the config.
+    ///
+    /// The config file is read again when asked to (see `reloader()`), or
+    /// whenever it changes if `watch` is set in it. Everything apart from the
+    /// addresses and the number of workers can be changed this way, and new
+    /// connections are handled with the new config while open ones finish
+    /// with the old one. A config that is not valid is reported and ignored.
+    ///
+    /// When asked to shut down (see `stopper()`), the server stops accepting
+    /// connections and closes the idle ones, then gives the requests it has
+    /// until `shutdown_timeout` to be answered before closing their
+    /// connections too
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut config = server::Config::load("config.txt").unwrap();
+    /// config.listen = vec![String::from("127.0.0.1:0")];
+    ///
+    /// let server = server::Server::new(config.workers);
+    /// let handle = server.start(config).unwrap();
+    /// // Join or shut down the server later
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// If an address could not be listened on, the access log could not be
+    /// opened, the files the config names cannot be used or signals are to
+    /// be handled where they cannot be
+    ///
+    /// # Panics
+    ///
+    /// If the access log could not be written to
+    pub fn start(mut self, mut config: Config) -> io::Result<ServerHandle> {
+        if self.handles_signals {
+            signals::install(&self.waker)?;
+        }
+        let listeners = config
+            .listen
+            .iter()
+            .map(|addr| {
+                let listener = TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(listener)
+            })
+            .collect::<io::Result<Vec<TcpListener>>>()?;
+        let mut is_debug = wants_debug(&config);
+        let mut file = open_access_log(&config)?;
+        let mut parser = Parse::from_config(&config)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        parser.set_router(self.router.clone());
+        parser.middleware = self.middleware.clone();
+        let mut watcher = watch(&config);
+        print_warnings(&config);
+        let connections = Connections::new();
+        let stopper = self.stopper.clone();
         // Start the server on another thread to avoid blocking the main
         // thread ever
         let thread = thread::Builder::new()
             .name("server_thread".to_string())
-            .spawn(move || loop {
+            .spawn(move || {
+            let stop = loop {
+                let signalled = signals::pending();
+                if signalled.stop {
+                    self.stopper.stop();
+                }
+                if signalled.reopen {
+                    match open_access_log(&config) {
+                        Ok(reopened) => file = reopened,
+                        Err(err) => {
+                            println!("Could not reopen the access log: {}", err)
+                        }
+                    }
+                }
+                let changed = watcher.as_mut().is_some_and(Watcher::changed);
+                if self.reloader.take() || changed || signalled.reload {
+                    // Only swapped in once everything has been checked,
+                    // so a bad config leaves the server as it was
+                    match reload(&config, &parser) {
+                        Ok((new_config, new_parser, new_file)) => {
+                            config = new_config;
+                            parser = new_parser;
+                            file = new_file;
+                            is_debug = wants_debug(&config);
+                            watcher = watch(&config);
+                            print_warnings(&config);
+                            if config.log_level >= LogLevel::Info {
+                                println!("Reloaded the config");
+                            }
+                        }
+                        Err(err) => {
+                            println!("Not reloading the config: {}", err)
+                        }
+                    }
+                }
+
+                if let Some(stop) = self.stopper.requested() {
+                    break stop;
+                }
+                // The workers are gone, so there is nothing to drain
                 if self.is_dead() {
-                    thread::sleep(Duration::from_millis(
-                        (self.workers * 200).try_into().unwrap(),
-                    ));
-                    break;
+                    return ShutdownReport::default();
                 }
 
-                match listener.accept() {
-                    Ok((stream, addr)) => {
-                        let parser = parser.make_copy();
-                        self.execute(move || {
-                            parser.handle(stream, is_debug);
-                        });
-                        let time: DateTime<Local> = Local::now();
-                        file.write_all(
-                            format!("{:?} at {}\n", addr, time).as_bytes(),
-                        )
-                        .unwrap();
+                // Sleeps until there is a connection or something else to do,
+                // waking up in time for the next look at a watched config
+                let interval = watcher.as_ref().map(|_| WATCH_INTERVAL);
+                let ready = match poll::wait(&listeners, &self.waker, interval)
+                {
+                    Ok(ready) => ready,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
+                        continue
                     }
-                    Err(ref e)
-                        if e.kind() == std::io::ErrorKind::WouldBlock =>
-                    {
-                        thread::sleep(Duration::from_millis(500));
+                    Err(e) => {
+                        println!("Could not wait for connections: {}", e);
+                        thread::sleep(ACCEPT_BACKOFF);
+                        continue;
                     }
-                    Err(e) => panic!("Err: {}", e),
                 };
-            })
-            .unwrap();
-        return thread;
+                for listener in ready.into_iter().map(|i| &listeners[i]) {
+                    // Several connections may be waiting on one readiness
+                    loop {
+                        match listener.accept() {
+                            Ok((stream, addr)) => {
+                                let tracked = match connections.add(&stream) {
+                                    Ok(tracked) => tracked,
+                                    Err(err) => {
+                                        println!(
+                                            "Could not clone stream: {}",
+                                            err
+                                        );
+                                        continue;
+                                    }
+                                };
+                                let parser = parser.make_copy();
+                                self.execute(move || {
+                                    parser.handle_connection(
+                                        stream,
+                                        is_debug,
+                                        Some(&tracked),
+                                    );
+                                });
+                                if let Some(file) = file.as_mut() {
+                                    let time: DateTime<Local> = Local::now();
+                                    file.write_all(
+                                        format!("{:?} at {}\n", addr, time)
+                                            .as_bytes(),
+                                    )
+                                    .unwrap();
+                                }
+                            }
+                            Err(ref e)
+                                if e.kind()
+                                    == std::io::ErrorKind::WouldBlock =>
+                            {
+                                break
+                            }
+                            // The client gave up before it was accepted
+                            Err(ref e)
+                                if matches!(
+                                    e.kind(),
+                                    io::ErrorKind::Interrupted
+                                        | io::ErrorKind::ConnectionAborted
+                                        | io::ErrorKind::ConnectionReset
+                                ) =>
+                            {
+                                continue
+                            }
+                            // Usually out of file descriptors, which only
+                            // gets better once some connections are closed,
+                            // so the ones waiting are left for a moment
+                            Err(e) => {
+                                println!(
+                                    "Could not accept a connection: {}",
+                                    e
+                                );
+                                poll::wait(
+                                    &[],
+                                    &self.waker,
+                                    Some(ACCEPT_BACKOFF),
+                                )
+                                .ok();
+                                break;
+                            }
+                        };
+                    }
+                }
+            };
+
+            // Nothing new is accepted from here on
+            drop(listeners);
+            let timeout = stop
+                .timeout
+                .unwrap_or(Duration::from_secs(config.shutdown_timeout));
+            if config.log_level >= LogLevel::Info {
+                println!("Shutting down, waiting up to {:?}", timeout);
+            }
+            let deadline = Instant::now() + timeout;
+            let report = connections.drain(timeout);
+            // Workers still stuck on a request past the deadline are not
+            // waited for. Their requests were counted as aborted when their
+            // connections were closed at the deadline
+            let running = self.threadpool.kill_by(deadline);
+            if running > 0 && config.log_level >= LogLevel::Warn {
+                println!(
+                    "Left {} busy worker{} behind",
+                    running,
+                    if running == 1 { "" } else { "s" }
+                );
+            }
+            if config.log_level >= LogLevel::Info {
+                println!("Shut down: {}", report);
+            }
+            report
+        })?;
+        Ok(ServerHandle::new(thread, stopper))
+    }
+}
+
+/// Returns whether a request says it does not accept responses that are
+/// not compressed
+fn refuses_identity(request: &Request) -> bool {
+    request
+        .header("Accept-Encoding")
+        .is_some_and(|accept| negotiate_encoding(accept, &[]).is_none())
+}
+
+/// Function to tell whether every request and response should be printed.
+/// The environment variable 'debug' can be set to 1 for useful debugging
+/// purposes, on top of the log level in the config
+fn wants_debug(config: &Config) -> bool {
+    env::var("debug").is_ok() || config.log_level == LogLevel::Debug
+}
+
+/// Function to print what may be wrong with a config (see
+/// `Config::warnings()`), unless only errors are to be printed
+fn print_warnings(config: &Config) {
+    if config.log_level >= LogLevel::Warn {
+        for warning in config.warnings() {
+            println!("Warning: {}", warning);
+        }
+    }
+}
+
+/// Function to open the file the address of every client is written to, if
+/// the config names one
+fn open_access_log(config: &Config) -> io::Result<Option<File>> {
+    match &config.access_log {
+        Some(path) => Ok(Some(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        )),
+        None => Ok(None),
     }
 }
 
+/// Function to start watching the config file for changes, if the config
+/// asks for it and was read from a file
+fn watch(config: &Config) -> Option<Watcher> {
+    config.path().filter(|_| config.watch).map(Watcher::new)
+}
+
+/// Function to read the config of a running server again (see
+/// `Config::reload()`), along with everything made from it. The addresses
+/// and the number of workers are kept, as they cannot change while the
+/// server runs, and so are the routes and middleware of the old parser
+fn reload(
+    config: &Config,
+    parser: &Parse,
+) -> Result<(Config, Parse, Option<File>), ConfigError> {
+    let mut new_config = config.reload()?;
+    new_config.listen = config.listen.clone();
+    new_config.workers = config.workers;
+    let mut new_parser = Parse::from_config(&new_config)?;
+    new_parser.router = Arc::clone(&parser.router);
+    new_parser.middleware = parser.middleware.clone();
+    let file = open_access_log(&new_config).map_err(|err| {
+        ConfigError::new(
+            &new_config
+                .path()
+                .unwrap_or(Path::new("config"))
+                .display()
+                .to_string(),
+            &format!("could not open the access log: {}", err),
+        )
+    })?;
+    Ok((new_config, new_parser, file))
+}
+
 impl Drop for Server {
     fn drop(&mut self) {
         // If for some reason the server is unexpectedly shut off, make sure
         // that the threadpool has been shut off
         self.threadpool.kill();
-        if let Some(thread) = self.input_thread.take() {
-            thread.join().unwrap();
-        }
     }
 }
 
+#[derive(Clone)]
 pub struct Parse {
-    index: String,
-    error_404: String,
+    index: Vec<String>,
     has_index: bool,
-    has_error: bool,
+    pages: ErrorPages,
+    keep_alive_timeout: u64,
+    max_requests: usize,
+    root: DocumentRoot,
+    max_body: u64,
+    upload_dir: PathBuf,
+    max_upload: u64,
+    publisher: Publisher,
+    mime: MimeTypes,
+    encodings: Vec<Encoding>,
+    compress_min_size: u64,
+    precompressed: bool,
+    autoindex: Vec<String>,
+    router: Arc<Router>,
+    middleware: Vec<Arc<dyn Middleware>>,
 }
 
 impl Parse {
-    /// Function to create a new parser for any http requests. Requires the
-    /// name of the index file and the 404 error file and uses a dummy one if
-    /// none are provided.
+    /// Function to create a new parser for any http requests from a config
+    /// file (see `Config`). A dummy index and 404 page are used if the config
+    /// does not name any
     ///
     /// # Examples
     ///
@@ -175,57 +588,124 @@ impl Parse {
     ///
     /// # Panics
     ///
-    /// - If the config file could not be opened
-    /// - If the iterator returned by BufReader contains Err value (should not
-    ///   happen)
+    /// - If the config file could not be read or is not valid
+    /// - If the document root is not a readable directory
     pub fn new(filename: &str) -> Parse {
-        let config = File::open(filename).unwrap();
-        let mut index = String::new();
-        let mut error_404 = String::new();
-        let mut has_index = true;
-        let mut has_error = true;
-        for line in BufReader::new(config).lines() {
-            let line = line.unwrap();
-            if line.starts_with("index:") {
-                index = String::from(line).split(' ').collect::<Vec<&str>>()[1]
-                    .to_string();
-            } else if line.starts_with("404:") {
-                error_404 =
-                    String::from(line).split(' ').collect::<Vec<&str>>()[1]
-                        .to_string();
-            // '#' is for comments
-            } else if !line.starts_with("#") && line.len() > 0 {
-                println!("Garbage in config file: {}", line);
-            }
-        }
+        Config::load(filename)
+            .and_then(|config| Parse::from_config(&config))
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        if index.is_empty() {
-            println!("No index file provided. Using dummy file.");
-            has_index = false;
+    /// Function to create a new parser from a config. The files the config
+    /// names are read here, and the config file itself is never served
+    ///
+    /// # Errors
+    ///
+    /// If the document root is not a readable directory, or the error pages
+    /// or the mime types file could not be read
+    pub fn from_config(config: &Config) -> Result<Parse, ConfigError> {
+        let file = config
+            .path()
+            .map_or(String::from("config"), |path| path.display().to_string());
+        let error = |message: String| ConfigError::new(&file, &message);
+
+        let mut pages = ErrorPages::new();
+        for (code, page) in &config.error_pages {
+            pages.load(*code, &page.to_string_lossy()).map_err(|err| {
+                error(format!(
+                    "could not read the {} page {}: {}",
+                    code,
+                    page.display(),
+                    err
+                ))
+            })?;
+        }
+        let mut mime = MimeTypes::new();
+        if let Some(types) = &config.mime_file {
+            mime.load(&types.to_string_lossy()).map_err(|err| {
+                error(format!(
+                    "could not read mime types from {}: {}",
+                    types.display(),
+                    err
+                ))
+            })?;
         }
-        if error_404.is_empty() {
-            println!("No 404 file provided. Using dummy file.");
-            has_error = false;
+        for (extension, content_type) in &config.mime_types {
+            mime.insert(extension, content_type);
         }
+        mime.set_default(&config.default_type);
 
-        Parse {
-            index,
-            error_404,
-            has_index,
-            has_error,
+        // The config file itself is never served, whatever it is called
+        let mut deny = sandbox::DEFAULT_DENY
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<String>>();
+        deny.extend(config.deny.iter().cloned());
+        if let Some(name) = config.path().and_then(Path::file_name) {
+            deny.push(name.to_string_lossy().to_string());
         }
+        let root = DocumentRoot::new(&config.root.to_string_lossy(), deny)
+            .map_err(|err| {
+                error(format!(
+                    "could not use {} as the document root: {}",
+                    config.root.display(),
+                    err
+                ))
+            })?;
+
+        Ok(Parse {
+            index: config.index.clone(),
+            has_index: !config.index.is_empty(),
+            pages,
+            keep_alive_timeout: config.keep_alive_timeout,
+            max_requests: config.max_requests,
+            root,
+            max_body: config.max_body,
+            upload_dir: config.upload_dir.clone(),
+            max_upload: config.max_upload,
+            publisher: Publisher::new(
+                config.writable.clone(),
+                config.users.clone(),
+            ),
+            mime,
+            encodings: config.compress.clone(),
+            compress_min_size: config.compress_min_size,
+            precompressed: config.precompressed,
+            autoindex: config.autoindex.clone(),
+            router: Arc::new(Router::new()),
+            middleware: Vec::new(),
+        })
+    }
+
+    /// Function to set the routes the parser tries before it looks for a
+    /// file
+    pub fn set_router(&mut self, router: Router) {
+        self.router = Arc::new(router);
     }
 
-    /// Function to handle any http requests the parser gets. Does not check
-    /// if the file being given is allowed or not (i.e allows access to any
-    /// readable file). Does not keep the connection alive yet
+    /// Function to wrap middleware around the handling of every request,
+    /// inside of any middleware added before it
+    pub fn wrap<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Function to handle any http requests the parser gets. Requests that
+    /// match a route (see `set_router()`) are answered by its handler, the
+    /// rest from the files under the document root, but never dotfiles or
+    /// the files on the deny list (see `DocumentRoot::resolve()`). Keeps the
+    /// connection alive for as long as the client wants to, serving requests
+    /// in the order they were sent, until it has been idle for
+    /// `keep_alive_timeout` seconds or `max_requests` requests have been
+    /// served. Requests that cannot be parsed are answered with the
+    /// matching error status (e.g. `400 Bad Request`) and the connection is
+    /// closed
     ///
     /// # Examples
     ///
     /// ```
     /// use std::net::TcpListener;
     ///
-    /// let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
     /// let parser = server::Parse::new("config.txt");
     ///
     /// listener.set_nonblocking(true).unwrap();
@@ -239,86 +719,544 @@ impl Parse {
     ///     _ => {},
     /// };
     /// ```
-    ///
-    /// # Panics
-    ///
-    /// - If the TcpStream could not be read from
-    /// - If the file to be sent could not be opened
-    pub fn handle(&self, mut stream: TcpStream, is_debug: bool) {
-        let mut buffer = [0; 512];
-        stream.read(&mut buffer).unwrap();
+    pub fn handle(&self, stream: TcpStream, is_debug: bool) {
+        self.handle_connection(stream, is_debug, None);
+    }
 
-        if is_debug {
-            println!(
-                "\n----------\n\n{}",
-                String::from_utf8(buffer.to_vec()).unwrap()
-            );
+    /// Function to handle the requests on a connection, as for `handle()`.
+    /// A tracked connection is marked busy while it has a request, and is
+    /// closed between requests once the server is shutting down
+    fn handle_connection(
+        &self,
+        stream: TcpStream,
+        is_debug: bool,
+        tracked: Option<&Tracked>,
+    ) {
+        // A timeout of zero means connections are never kept alive, but reads
+        // still need some timeout so idle clients cannot hold a worker forever
+        let timeout = Duration::from_secs(self.keep_alive_timeout.max(1));
+        if let Err(err) = stream.set_read_timeout(Some(timeout)) {
+            println!("Could not set read timeout: {}", err);
+            return;
         }
+        // Reads go through a buffer so the request can be parsed line by line
+        // and any pipelined requests stay buffered until they are handled
+        let mut reader = match stream.try_clone() {
+            Ok(stream) => BufReader::new(stream),
+            Err(err) => {
+                println!("Could not clone stream: {}", err);
+                return;
+            }
+        };
+        let mut stream = stream;
+        let mut served = 0;
+        loop {
+            let mut request = match Request::read_head(&mut reader) {
+                Ok(request) => request,
+                Err(err) => {
+                    // A closed or broken connection gets no response, so it
+                    // does not take a request ID either
+                    if let Ok(response) = rejection(&err, is_debug) {
+                        let reply = self.reply(None, false, is_debug);
+                        send_response(&mut stream, response, reply).ok();
+                    }
+                    return;
+                }
+            };
+            served += 1;
+            if let Some(tracked) = tracked {
+                tracked.busy();
+            }
 
-        // The type of requests possible
-        let get = b"GET";
-        let post = b"POST";
-        let put = b"PUT";
+            if is_debug {
+                println!("\n----------\n\n{}", request);
+            }
 
-        if buffer.starts_with(get) {
-            let mut file_path = String::from(
-                String::from_utf8(buffer.to_vec())
-                    .unwrap()
-                    .split(' ')
-                    .collect::<Vec<&str>>()[1],
-            );
-            // If the user provided no index file use our own
-            let filename = if file_path == "/" {
-                if self.has_index {
-                    self.index.as_str()
+            let keep_alive = request.keep_alive()
+                && self.keep_alive_timeout > 0
+                && served < self.max_requests
+                && !tracked.is_some_and(Tracked::is_stopping);
+            // Whether the middleware let the request through to be handled,
+            // which is where its body is dealt with
+            let mut reached = false;
+            let response =
+                middleware::run(&self.middleware, &mut request, |request| {
+                    reached = true;
+                    self.respond(&mut stream, request, &mut reader, is_debug)
+                });
+            // A body nobody read is still in the way of the next request
+            let response = response.map(|response| {
+                if reached || request.content_length() == Some(0) {
+                    response
                 } else {
-                    "dummy.html"
+                    response.with_header("Connection", "close")
                 }
-            // Otherwise just give them the file. There is no checking for
-            // what file is being sent right now
-            } else {
-                file_path.remove(0);
-                file_path.as_str()
-            };
-            if is_debug {
-                println!("file_name: {}", filename);
+            });
+            let sent = response.and_then(|response| {
+                let reply = self.reply(Some(&request), keep_alive, is_debug);
+                send_response(&mut stream, response, reply)
+            });
+            if let (Ok(_), Some(tracked)) = (&sent, tracked) {
+                tracked.answered();
             }
-            let contents: String;
-            let mut response_type: &str;
-            let mut content_type = check_content(&String::from(filename));
-            // If neither index or 404 files are available use a dummy file
-            if !self.has_index && file_path == "/" && !self.has_error {
-                contents =
-                    "<!DOCTYPE html><html><body>No index file</body></html>"
-                        .to_string();
-                response_type = "200 OK";
-            } else {
-                response_type = "200 OK";
-                contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-                    response_type = "404 NOT FOUND";
-                    content_type = "text/html".to_string();
-                    if self.has_error {
-                        fs::read_to_string(self.error_404.as_str()).unwrap()
-                    } else {
-                        "<!DOCTYPE html><html><body>No 404 file</body></html>"
-                            .to_string()
-                    }
+            // The response may have had to close the connection to end
+            if !matches!(sent, Ok(true)) {
+                return;
+            }
+            if !tracked.is_none_or(Tracked::idle) {
+                return;
+            }
+        }
+    }
+
+    /// Function to answer a request that got through the middleware of the
+    /// server, with a route if one matches or else from the files under the
+    /// document root
+    fn respond<R: BufRead>(
+        &self,
+        stream: &mut TcpStream,
+        request: &mut Request,
+        reader: &mut R,
+        is_debug: bool,
+    ) -> io::Result<Response> {
+        // Forms and published files are read straight from the connection
+        // so that they never have to be held in memory, any other body is
+        // read here. Routes only get the request, so they always get the
+        // whole body
+        let routed = self.router.matches(request.path());
+        if routed || !matches!(request.method(), Method::Post | Method::Put) {
+            send_continue(stream, request)?;
+            if let Err(err) = request.read_body(reader, self.max_body) {
+                return rejection(&err, is_debug);
+            }
+        }
+        match self.router.handle(request) {
+            Some(response) => Ok(response),
+            None => self.serve(stream, request, reader, is_debug),
+        }
+    }
+
+    /// Function to answer a request no route matched, from the files under
+    /// the document root
+    fn serve<R: BufRead>(
+        &self,
+        stream: &mut TcpStream,
+        request: &Request,
+        reader: &mut R,
+        is_debug: bool,
+    ) -> io::Result<Response> {
+        match request.method() {
+            Method::Post => self.post(stream, request, reader, is_debug),
+            Method::Put => self.put(stream, request, reader, is_debug),
+            Method::Get | Method::Head => Ok(self.get(request, is_debug)),
+            Method::Delete => self.delete(request, is_debug),
+            Method::Options => Ok(self.options(request)),
+            // Methods nobody has heard of are not implemented, the others
+            // are known but not allowed here
+            Method::Other(_) => Ok(Response::new(StatusCode::NotImplemented)),
+            _ => Ok(Response::new(StatusCode::MethodNotAllowed)
+                .with_header("Allow", &self.allowed(request))),
+        }
+    }
+
+    /// Function to answer a GET request with the file it asks for. A
+    /// directory is answered with the first of its index files that exists,
+    /// or a listing if those are turned on for it. Directories asked for
+    /// without a trailing slash are redirected to the path with one, so that
+    /// relative links in them work
+    fn get(&self, request: &Request, is_debug: bool) -> Response {
+        let request_path = request.path();
+        let path = match self.root.resolve(request_path) {
+            Ok(path) => path,
+            Err(err) => {
+                if is_debug {
+                    println!("{}: {}", err, request_path);
+                }
+                return Response::new(err.status());
+            }
+        };
+        if path.is_dir() {
+            return self.get_dir(request, &path, is_debug);
+        }
+        self.get_file(request, request_path, &path, is_debug)
+    }
+
+    /// Function to answer a GET request for a directory
+    fn get_dir(
+        &self,
+        request: &Request,
+        dir: &Path,
+        is_debug: bool,
+    ) -> Response {
+        let request_path = request.path();
+        if !request_path.ends_with('/') {
+            // Made from the path that was resolved rather than the one that
+            // was asked for, which could start with `//` and so name another
+            // host
+            let relative = dir.strip_prefix(self.root.path()).unwrap_or(dir);
+            let mut location = String::from("/");
+            for segment in relative.iter() {
+                let segment = segment.to_string_lossy();
+                location.push_str(&autoindex::encode_segment(&segment));
+                location.push('/');
+            }
+            if let Some(query) = request.query() {
+                location = format!("{}?{}", location, query);
+            }
+            return Response::new(StatusCode::MovedPermanently)
+                .with_header("Location", &location);
+        }
+
+        for name in &self.index {
+            let index_path =
+                format!("{}{}", request_path, autoindex::encode_segment(name));
+            if let Ok(path) = self.root.resolve(&index_path) {
+                if path.is_file() {
+                    return self.get_file(
+                        request,
+                        &index_path,
+                        &path,
+                        is_debug,
+                    );
+                }
+            }
+        }
+        if self.lists(dir) {
+            return self.list(request, dir);
+        }
+        // If neither index or 404 files are available use a dummy file
+        if !self.has_index && request_path == "/" && !self.pages.contains(404) {
+            return Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "text/html")
+                .with_body(
+                    "<!DOCTYPE html><html><body>No index file</body></html>",
+                );
+        }
+        self.not_found()
+    }
+
+    /// Function to answer a GET request for a file. `request_path` is the
+    /// path of the file as a client would ask for it
+    fn get_file(
+        &self,
+        request: &Request,
+        request_path: &str,
+        path: &Path,
+        is_debug: bool,
+    ) -> Response {
+        if is_debug {
+            println!("file_name: {}", path.display());
+        }
+        match open_file(path) {
+            Ok((file, metadata)) => {
+                let found = self.representation(
+                    request,
+                    request_path,
+                    path,
+                    file,
+                    metadata,
+                );
+                match found {
+                    Some(found) => found_response(request, found),
+                    None => Response::new(StatusCode::NotAcceptable)
+                        .with_header("Vary", "Accept-Encoding"),
+                }
+            }
+            Err(_) => self.not_found(),
+        }
+    }
+
+    /// Returns if directory listings are turned on for a directory
+    fn lists(&self, dir: &Path) -> bool {
+        let relative = dir.strip_prefix(self.root.path()).unwrap_or(dir);
+        let relative = relative.to_string_lossy();
+        self.autoindex.iter().any(|prefix| {
+            prefix.is_empty()
+                || relative == prefix.as_str()
+                || relative.starts_with(&format!("{}/", prefix))
+        })
+    }
+
+    /// Function to answer a request for a directory with a listing of it, as
+    /// a html page or as JSON. Files that could not be requested, like
+    /// dotfiles and denied files, are left out
+    fn list(&self, request: &Request, dir: &Path) -> Response {
+        let query = ListingQuery::parse(request.query());
+        let base = request.path().trim_end_matches('/');
+        let listing = Listing::read(dir, request.path(), |name| {
+            let path = format!("{}/{}", base, autoindex::encode_segment(name));
+            self.root.resolve(&path).is_ok()
+        });
+        let mut listing = match listing {
+            Ok(listing) => listing,
+            Err(_) => return Response::new(StatusCode::Forbidden),
+        };
+        listing.sort(query.sort, query.descending);
+
+        let response = Response::new(StatusCode::Ok);
+        if query.json {
+            response
+                .with_header("Content-Type", "application/json")
+                .with_body(listing.to_json())
+        } else {
+            response
+                .with_header("Content-Type", "text/html; charset=utf-8")
+                .with_body(listing.to_html(query))
+        }
+    }
+
+    /// Function to answer a POST request by reading the form in its body.
+    /// The response lists what was received, and uploaded files are deleted
+    /// again once they have been counted. If the form cannot be read the rest of the
+    /// body is still unread, so the error is sent and the connection is
+    /// closed. An error is returned if not even that is possible
+    fn post<R: BufRead>(
+        &self,
+        stream: &mut TcpStream,
+        request: &Request,
+        reader: &mut R,
+        is_debug: bool,
+    ) -> io::Result<Response> {
+        let limits =
+            FormLimits::new(&self.upload_dir, self.max_body, self.max_upload);
+        send_continue(stream, request)?;
+        let form = match Form::read_from(request, reader, &limits) {
+            Ok(form) => form,
+            Err(err) => {
+                if is_debug {
+                    println!("Invalid form: {}", err);
+                }
+                return match err.status() {
+                    Some(status) => Ok(Response::new(status)
+                        .with_header("Connection", "close")),
+                    None => Err(io::Error::other(err.to_string())),
+                };
+            }
+        };
+
+        let mut summary = format!(
+            "Received {} field(s) and {} file(s)\n",
+            form.fields().count(),
+            form.files().len()
+        );
+        for file in form.files() {
+            summary.push_str(&format!(
+                "{}: {} ({} bytes)\n",
+                file.field(),
+                file.filename(),
+                file.size()
+            ));
+        }
+        // Nothing is done with the files here, so they are not kept. Routes
+        // that want them read the form themselves (see `Form::from_request()`)
+        form.remove_files();
+        Ok(Response::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain")
+            .with_body(summary))
+    }
+
+    /// Function to answer a PUT request by publishing its body as the file it
+    /// names. Only logged in users may publish, and only under the writable
+    /// paths. On failure the connection is closed after the error is sent,
+    /// as the body may still be unread
+    fn put<R: BufRead>(
+        &self,
+        stream: &mut TcpStream,
+        request: &Request,
+        reader: &mut R,
+        is_debug: bool,
+    ) -> io::Result<Response> {
+        let published = self
+            .publisher
+            .authorize(request)
+            .and_then(|_| {
+                self.publisher
+                    .check_put(&self.root, request, self.max_upload)
+            })
+            .and_then(|path| {
+                send_continue(stream, request).map_err(PublishError::Io)?;
+                self.publisher.put(&path, request, reader, self.max_upload)
+            });
+        match published {
+            Ok(Published::Created) => Ok(Response::new(StatusCode::Created)
+                .with_header("Location", request.path())),
+            Ok(Published::Replaced) => Ok(Response::new(StatusCode::NoContent)),
+            Err(err) => Ok(publish_error(&err, is_debug)?
+                .with_header("Connection", "close")),
+        }
+    }
+
+    /// Function to answer a DELETE request by removing the file it names.
+    /// The same rules as for PUT decide who may do this
+    fn delete(
+        &self,
+        request: &Request,
+        is_debug: bool,
+    ) -> io::Result<Response> {
+        let deleted = self
+            .publisher
+            .authorize(request)
+            .and_then(|_| self.publisher.delete(&self.root, request));
+        match deleted {
+            Ok(()) => Ok(Response::new(StatusCode::NoContent)),
+            Err(err) => publish_error(&err, is_debug),
+        }
+    }
+
+    /// Function to answer an OPTIONS request with the methods that can be
+    /// used on the resource it names, or on the server as a whole for `*`
+    fn options(&self, request: &Request) -> Response {
+        Response::new(StatusCode::NoContent)
+            .with_header("Allow", &self.allowed(request))
+    }
+
+    /// Function to list the methods that can be used on the resource a
+    /// request names, by files and by routes. PUT and DELETE are only listed
+    /// for files where they can be published
+    fn allowed(&self, request: &Request) -> String {
+        let mut allowed = vec!["GET", "HEAD", "POST", "OPTIONS"];
+        let (writable, routed) = if request.target() == "*" {
+            (self.publisher.is_enabled(), self.router.methods())
+        } else {
+            (
+                self.publisher.is_writable(&self.root, request),
+                self.router.allowed(request.path()),
+            )
+        };
+        if writable {
+            allowed.push("PUT");
+            allowed.push("DELETE");
+        }
+        for method in &routed {
+            if !allowed.contains(&method.as_str()) {
+                allowed.push(method.as_str());
+            }
+        }
+        allowed.join(", ")
+    }
+
+    /// Function to pick what to send for a file that was found: a
+    /// precompressed copy stored next to it (`name.br` or `name.gz`) if the
+    /// client accepts one, or else the file itself, compressed on the fly if
+    /// that is worth it. Returns None if the client accepts none of these
+    fn representation(
+        &self,
+        request: &Request,
+        request_path: &str,
+        path: &Path,
+        file: File,
+        metadata: Metadata,
+    ) -> Option<Found> {
+        let content_type = self.mime.lookup(&path.to_string_lossy());
+        let mut vary = false;
+        if self.precompressed {
+            let siblings: Vec<(Encoding, PathBuf)> = Encoding::ALL
+                .iter()
+                .filter_map(|encoding| {
+                    let extension = encoding.extension()?;
+                    let sibling = format!("{}{}", request_path, extension);
+                    let sibling = self.root.resolve(&sibling).ok()?;
+                    Some((*encoding, sibling))
+                })
+                .filter(|(_, sibling)| sibling.is_file())
+                .collect();
+            vary = !siblings.is_empty();
+            let available: Vec<Encoding> =
+                siblings.iter().map(|(encoding, _)| *encoding).collect();
+            let encoding = request
+                .header("Accept-Encoding")
+                .and_then(|accept| negotiate_encoding(accept, &available))
+                .unwrap_or(Encoding::Identity);
+            let sibling = siblings
+                .into_iter()
+                .find(|(stored, _)| *stored == encoding)
+                .and_then(|(_, sibling)| open_file(sibling).ok());
+            if let Some((file, metadata)) = sibling {
+                return Some(Found {
+                    file,
+                    metadata,
+                    content_type,
+                    vary,
+                    encoding,
+                    precompressed: true,
                 });
-            };
-            let status_line = format!(
-                "HTTP/1.1 {}\r\nContent-Type: {}\r\n\r\n",
-                response_type, content_type
-            );
-            let response = format!("{}{}", status_line, contents);
-            if is_debug {
-                println!("response: \n{}\n----------\n", response);
             }
-            stream.write(response.as_bytes()).unwrap();
-        } else if buffer.starts_with(post) {
-        } else if buffer.starts_with(put) {
+        }
+
+        let encoding = self.encoding(request, &content_type, metadata.len());
+        let vary = vary || encoding.is_some();
+        let encoding = encoding.unwrap_or(Encoding::Identity);
+        // Clients may refuse the file as it is, and then there is nothing
+        // left to send them
+        if encoding == Encoding::Identity && refuses_identity(request) {
+            return None;
+        }
+        Some(Found {
+            file,
+            metadata,
+            content_type,
+            vary,
+            encoding,
+            precompressed: false,
+        })
+    }
+
+    /// Function to decide how a file should be compressed. Returns None if
+    /// it never is, whatever the client accepts, so the response does not
+    /// depend on Accept-Encoding. Ranges and HTTP/1.0 clients (which cannot
+    /// take a chunked body) always get the file as it is. Files that are not
+    /// worth compressing still are for clients that refuse them as they are
+    fn encoding(
+        &self,
+        request: &Request,
+        content_type: &str,
+        length: u64,
+    ) -> Option<Encoding> {
+        let worth_it =
+            length >= self.compress_min_size && is_compressible(content_type);
+        if self.encodings.is_empty() || !(worth_it || refuses_identity(request))
+        {
+            return None;
+        }
+        let is_range = request.method() == &Method::Get
+            && request.header("Range").is_some();
+        match request.header("Accept-Encoding") {
+            Some(accept)
+                if !is_range && request.version() == Version::Http11 =>
+            {
+                Some(
+                    negotiate_encoding(accept, &self.encodings)
+                        .unwrap_or(Encoding::Identity),
+                )
+            }
+            _ => Some(Encoding::Identity),
+        }
+    }
+
+    /// Function to start the reply to a request, or to a request that could
+    /// not be read at all. Every reply gets a new request ID
+    fn reply<'a>(
+        &'a self,
+        request: Option<&'a Request>,
+        keep_alive: bool,
+        is_debug: bool,
+    ) -> Reply<'a> {
+        Reply {
+            request,
+            keep_alive,
+            head_only: request
+                .is_some_and(|request| request.method() == &Method::Head),
+            is_debug,
+            pages: &self.pages,
+            request_id: next_request_id(),
         }
     }
 
+    /// Returns the 404 response
+    fn not_found(&self) -> Response {
+        Response::new(StatusCode::NotFound)
+    }
+
     /// Function to make a copy of a parser. Used in the server to prevent
     /// repeatedly spamming stdout if no index or 404 file is given as a new
     /// parser is made every iteration of the loop
@@ -331,43 +1269,255 @@ impl Parse {
     /// // Move other_parser into a thread if needed
     /// ```
     pub fn make_copy(&self) -> Parse {
-        let index = String::from_str(self.index.as_str()).unwrap();
-        let error_404 = String::from_str(self.error_404.as_str()).unwrap();
-        let has_index = self.has_index;
-        let has_error = self.has_error;
+        self.clone()
+    }
+}
+
+/// Function to get a new request ID
+fn next_request_id() -> u64 {
+    REQUEST_COUNT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Function to write a request ID the way it is sent to clients
+fn format_request_id(request_id: u64) -> String {
+    format!("{:08x}", request_id)
+}
+
+/// Function to open a regular file for reading, returning it along with its
+/// metadata. Directories and other special files are reported as not found
+fn open_file<P: AsRef<Path>>(path: P) -> io::Result<(File, Metadata)> {
+    let file = File::open(path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "not a file"));
+    }
+    Ok((file, metadata))
+}
+
+/// How a response should be sent back on a connection
+#[derive(Clone, Copy)]
+struct Reply<'a> {
+    /// The request being answered, None if it could not be read
+    request: Option<&'a Request>,
+    /// Whether the connection stays open afterwards
+    keep_alive: bool,
+    /// Whether only the head is sent, as for HEAD requests
+    head_only: bool,
+    is_debug: bool,
+    /// The pages sent with error statuses
+    pages: &'a ErrorPages,
+    /// Sent as X-Request-Id and shown on error pages, so a response can be
+    /// told apart from all others
+    request_id: u64,
+}
+
+/// Function to send a response. Error statuses that were not given a body
+/// get the error page for them, and every response gets the request ID.
+/// Returns if the connection can be used for another request
+fn send_response(
+    stream: &mut TcpStream,
+    mut response: Response,
+    reply: Reply,
+) -> io::Result<bool> {
+    let request_id = format_request_id(reply.request_id);
+    let status = response.status();
+    if status.is_error() && response.body().is_empty() {
+        let path = reply.request.map_or("", Request::path);
+        let page = reply.pages.render(status, path, &request_id);
+        response
+            .headers_mut()
+            .insert("Content-Type", "text/html; charset=utf-8");
+        response.set_body(page);
+    }
+    response.headers_mut().insert("X-Request-Id", &request_id);
+    let keep_alive = response.prepare(reply.request, reply.keep_alive);
+    if reply.is_debug {
+        println!("response: \n{}----------\n", response.head());
+    }
+    response.write_prepared(stream, reply.head_only)?;
+    Ok(keep_alive)
+}
+
+/// A file that was found for a request, and how it is to be sent
+struct Found {
+    file: File,
+    metadata: Metadata,
+    content_type: String,
+    /// Whether what is sent depends on Accept-Encoding
+    vary: bool,
+    /// The coding the body is sent in
+    encoding: Encoding,
+    /// Whether the file is stored in that coding already, rather than still
+    /// having to be compressed
+    precompressed: bool,
+}
+
+/// Function to make the response for a file that was asked for, or for just
+/// the parts of it a Range header names. The conditional headers of the
+/// request are checked first, so a client that already has the file gets 304
+/// Not Modified. Ranges are only honoured for GET requests, and only if an
+/// If-Range header (when there is one) says the file has not changed. A file
+/// compressed on the fly is sent in the chunked transfer coding. Compressed
+/// files have their own entity tags
+fn found_response(request: &Request, found: Found) -> Response {
+    let Found {
+        mut file,
+        metadata,
+        content_type,
+        vary,
+        encoding,
+        precompressed,
+    } = found;
+    let length = metadata.len();
+    let modified = metadata.modified().ok();
+    let mut etag = file_etag(&metadata);
+    if encoding != Encoding::Identity {
+        etag = format!("{}-{}\"", etag.trim_end_matches('"'), encoding);
+    }
+
+    let mut response = Response::new(StatusCode::Ok);
+    let headers = response.headers_mut();
+    if vary {
+        headers.append("Vary", "Accept-Encoding");
+    }
+    headers.append("ETag", &etag);
+    if let Some(modified) = modified {
+        headers.append("Last-Modified", &format_http_date(modified));
+    }
+    match check_preconditions(request, &etag, modified) {
+        Precondition::Proceed => {}
+        Precondition::NotModified => {
+            response.set_status(StatusCode::NotModified);
+            return response;
+        }
+        Precondition::Failed => {
+            response.set_status(StatusCode::PreconditionFailed);
+            return response;
+        }
+    }
+
+    let ranges = match request.header("Range") {
+        Some(range) if request.method() == &Method::Get => {
+            let unchanged = request.header("If-Range").is_none_or(|value| {
+                if_range_matches(value, modified, Some(&etag))
+            });
+            if unchanged {
+                Ranges::parse(range, length)
+            } else {
+                Ranges::Full
+            }
+        }
+        _ => Ranges::Full,
+    };
+
+    let headers = response.headers_mut();
+    headers.append("Accept-Ranges", "bytes");
+    if encoding != Encoding::Identity && ranges != Ranges::Unsatisfiable {
+        headers.append("Content-Encoding", encoding.as_str());
+    }
+    match ranges {
+        Ranges::Full if encoding != Encoding::Identity && !precompressed => {
+            headers.append("Content-Type", &content_type);
+            let body = compress_reader(file.take(length), encoding);
+            response.with_body(Body::Stream(body, None))
+        }
+        Ranges::Full => {
+            headers.append("Content-Type", &content_type);
+            response.with_file(file, length)
+        }
+        Ranges::Unsatisfiable => {
+            headers.append("Content-Range", &format!("bytes */{}", length));
+            response.set_status(StatusCode::RangeNotSatisfiable);
+            response
+        }
+        Ranges::Partial(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            headers.append("Content-Type", &content_type);
+            headers.append(
+                "Content-Range",
+                &format!("bytes {}-{}/{}", start, end, length),
+            );
+            if file.seek(SeekFrom::Start(start)).is_err() {
+                return Response::new(StatusCode::InternalServerError);
+            }
+            response.set_status(StatusCode::PartialContent);
+            response.with_file(file, end - start + 1)
+        }
+        Ranges::Partial(ranges) => {
+            let body =
+                MultipartRanges::new(file, ranges, &content_type, length);
+            headers.append("Content-Type", &body.content_type());
+            response.set_status(StatusCode::PartialContent);
+            let total = body.len();
+            response.with_stream(body, Some(total))
+        }
+    }
+}
 
-        Parse {
-            index,
-            error_404,
-            has_index,
-            has_error,
+/// Function to make the response for a request that could not be read,
+/// which closes the connection as the rest of the request is in the way.
+/// Returns an error if there is no status for it, so the connection should
+/// just be dropped
+fn rejection(err: &ParseError, is_debug: bool) -> io::Result<Response> {
+    if is_debug {
+        println!("\n----------\n\nInvalid request: {}", err);
+    }
+    match err.status() {
+        Some(status) => {
+            Ok(Response::new(status).with_header("Connection", "close"))
         }
+        None => Err(io::Error::other(err.to_string())),
     }
 }
 
+/// Function to make the response for a failed PUT or DELETE, asking the
+/// client to log in if that is what went wrong. Returns an error if the
+/// connection broke, so there is nobody to respond to
+fn publish_error(err: &PublishError, is_debug: bool) -> io::Result<Response> {
+    if is_debug {
+        println!("Could not publish: {}", err);
+    }
+    let status = match err.status() {
+        Some(status) => status,
+        None => return Err(io::Error::other(err.to_string())),
+    };
+    let mut response = Response::new(status);
+    if let Some(challenge) = err.challenge() {
+        response = response.with_header("WWW-Authenticate", &challenge);
+    }
+    Ok(response)
+}
+
+/// Function to tell a client that sent `Expect: 100-continue` to go ahead and
+/// send the body of its request
+fn send_continue(stream: &mut TcpStream, request: &Request) -> io::Result<()> {
+    if request.version() == Version::Http11
+        && request.headers().has_token("Expect", "100-continue")
+    {
+        let head = format!("HTTP/1.1 {}\r\n\r\n", StatusCode::Continue);
+        stream.write_all(head.as_bytes())?;
+    }
+    Ok(())
+}
+
 /// Function to check the content of the file based on the extension that the
-/// file has. Currently only checks for css and html files otherwise returns
-/// `text/plain`
+/// file has, using the built-in table of common web types. Text types get a
+/// UTF-8 charset and unknown extensions are `application/octet-stream`. The
+/// server itself also uses the types added in the config file, see
+/// `MimeTypes`
 ///
 /// # Examples
 ///
 /// ```
-/// // text/html
-/// let content_type = server::check_content(&"file.html".to_string());
+/// let content_type = server::check_content("file.html");
+/// assert_eq!(content_type, "text/html; charset=utf-8");
 ///
-/// // text/css
-/// let content_type = server::check_content(&"file.css".to_string());
+/// let content_type = server::check_content("photo.JPG");
+/// assert_eq!(content_type, "image/jpeg");
 ///
-/// // text/plain
-/// let content_type = server::check_content(&"foo.bar".to_string());
+/// let content_type = server::check_content("foo.bar");
+/// assert_eq!(content_type, "application/octet-stream");
 /// ```
-pub fn check_content(filename: &String) -> String {
-    // If we do not know the extension just send it as a plaintext file
-    if filename.ends_with(".css") {
-        "text/css".to_string()
-    } else if filename.ends_with(".html") {
-        "text/html".to_string()
-    } else {
-        "text/plain".to_string()
-    }
+pub fn check_content(filename: &str) -> String {
+    mime::builtin_type(filename)
 }
@@ -0,0 +1,80 @@
+/* See LICENSE for license details */
+use std::io;
+use std::sync::Arc;
+
+use crate::request::Request;
+use crate::response::Response;
+
+/// Logic wrapped around the handling of requests, like logging, checking
+/// credentials or adding headers. Middleware is run in the order it was
+/// added, so the first added is the outermost: its `before()` runs first and
+/// its `after()` last
+///
+/// # Examples
+///
+/// ```
+/// use server::{Middleware, Request, Response, StatusCode};
+///
+/// /// Turns away requests without an API key and marks every response
+/// struct ApiKey;
+///
+/// impl Middleware for ApiKey {
+///     fn before(&self, request: &mut Request) -> Option<Response> {
+///         match request.header("X-Api-Key") {
+///             Some("secret") => None,
+///             _ => Some(Response::new(StatusCode::Unauthorized)),
+///         }
+///     }
+///
+///     fn after(&self, _request: &Request, response: &mut Response) {
+///         response.headers_mut().insert("X-Checked", "yes");
+///     }
+/// }
+///
+/// let mut parser = server::Parse::new("config.txt");
+/// parser.wrap(ApiKey);
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Function called before the request is handled, which may change it.
+    /// Returning a response answers the request with it straight away, so
+    /// neither the handler nor the middleware inside this one see the
+    /// request. Server-wide middleware is called before the body of the
+    /// request is read
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// Function called with the response before it is sent, which may
+    /// change it. Only called if `before()` let the request through
+    fn after(&self, _request: &Request, _response: &mut Response) {}
+}
+
+/// Function to run a request through a chain of middleware, with `handler`
+/// as the innermost part of it. Errors from the handler are passed on as
+/// they are, as there is no response to run through the middleware
+pub(crate) fn run<F>(
+    middleware: &[Arc<dyn Middleware>],
+    request: &mut Request,
+    handler: F,
+) -> io::Result<Response>
+where
+    F: FnOnce(&mut Request) -> io::Result<Response>,
+{
+    let mut passed = 0;
+    let mut answered = None;
+    for layer in middleware {
+        answered = layer.before(request);
+        if answered.is_some() {
+            break;
+        }
+        passed += 1;
+    }
+    let mut response = match answered {
+        Some(response) => response,
+        None => handler(request)?,
+    };
+    for layer in middleware[..passed].iter().rev() {
+        layer.after(request, &mut response);
+    }
+    Ok(response)
+}
@@ -0,0 +1,189 @@
+/* See LICENSE for license details */
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, prelude::*, BufReader};
+
+/// The content type of files whose extension is not known
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// The extensions known without any configuration, with their content types
+const BUILTIN: &[(&str, &str)] = &[
+    // Text
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("webmanifest", "application/manifest+json"),
+    ("wasm", "application/wasm"),
+    // Images
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("bmp", "image/bmp"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    // Audio and video
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    // Documents and archives
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+];
+
+/// Types that are text even though they are not `text/*`, and so get a
+/// charset
+const TEXT_LIKE: &[&str] = &[
+    "application/json",
+    "application/manifest+json",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// Maps file extensions to content types. Starts out with a table of the
+/// common web types, which can be added to or overridden
+#[derive(Clone, Debug)]
+pub struct MimeTypes {
+    types: HashMap<String, String>,
+    default: String,
+}
+
+impl MimeTypes {
+    /// Function to create the built-in table
+    pub fn new() -> Self {
+        let types = BUILTIN
+            .iter()
+            .map(|(extension, content_type)| {
+                (extension.to_string(), content_type.to_string())
+            })
+            .collect();
+        MimeTypes {
+            types,
+            default: DEFAULT_TYPE.to_string(),
+        }
+    }
+
+    /// Function to add an extension, or change the type of a known one.
+    /// Extensions are matched without regard to case and may be given with
+    /// or without the leading dot
+    pub fn insert(&mut self, extension: &str, content_type: &str) {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        self.types.insert(extension, content_type.to_string());
+    }
+
+    /// Function to change the type sent for unknown extensions
+    pub fn set_default(&mut self, content_type: &str) {
+        self.default = content_type.to_string();
+    }
+
+    /// Function to add every type in a file in the `mime.types` format, where
+    /// each line holds a type followed by its extensions and `#` starts a
+    /// comment
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be read
+    pub fn load(&mut self, filename: &str) -> io::Result<()> {
+        for line in BufReader::new(File::open(filename)?).lines() {
+            let line = line?;
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            if let Some(content_type) = words.next() {
+                for extension in words {
+                    self.insert(extension.trim_end_matches(';'), content_type);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Function to get the Content-Type header for a file from the extension
+    /// of its name. Text types are given a UTF-8 charset
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut types = server::MimeTypes::new();
+    /// assert_eq!(types.lookup("app.JS"), "text/javascript; charset=utf-8");
+    /// assert_eq!(types.lookup("logo.png"), "image/png");
+    /// assert_eq!(types.lookup("README"), "application/octet-stream");
+    ///
+    /// types.insert("log", "text/plain");
+    /// assert_eq!(
+    ///     types.lookup("/var/server.log"),
+    ///     "text/plain; charset=utf-8"
+    /// );
+    /// ```
+    pub fn lookup(&self, filename: &str) -> String {
+        let content_type = extension(filename)
+            .and_then(|extension| self.types.get(&extension))
+            .unwrap_or(&self.default);
+        with_charset(content_type)
+    }
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        MimeTypes::new()
+    }
+}
+
+/// Function to look a file up in the built-in table only
+pub(crate) fn builtin_type(filename: &str) -> String {
+    let content_type = extension(filename)
+        .and_then(|extension| {
+            BUILTIN
+                .iter()
+                .find(|(known, _)| *known == extension)
+                .map(|(_, content_type)| *content_type)
+        })
+        .unwrap_or(DEFAULT_TYPE);
+    with_charset(content_type)
+}
+
+/// Function to get the lowercased extension of the last part of a path
+fn extension(filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or(filename);
+    let dot = name.rfind('.')?;
+    // A name like `.profile` has no extension
+    if dot == 0 {
+        return None;
+    }
+    Some(name[dot + 1..].to_lowercase())
+}
+
+/// Function to add a UTF-8 charset to text types that do not have one
+fn with_charset(content_type: &str) -> String {
+    if is_text(content_type) && !content_type.contains(';') {
+        format!("{}; charset=utf-8", content_type)
+    } else {
+        content_type.to_string()
+    }
+}
+
+/// Function to check if a content type is some kind of text, whatever its
+/// parameters
+pub(crate) fn is_text(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    let essence = essence.to_lowercase();
+    essence.starts_with("text/") || TEXT_LIKE.contains(&essence.as_str())
+}
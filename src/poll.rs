@@ -0,0 +1,160 @@
+/* See LICENSE for license details */
+use std::io;
+use std::net::TcpListener;
+use std::time::Duration;
+
+/// A way to wake up a thread waiting in `wait()`, from another thread or a
+/// signal handler. On unix this is a pipe the waiting thread also waits on
+#[derive(Debug)]
+pub(crate) struct Waker {
+    #[cfg(unix)]
+    read: libc::c_int,
+    #[cfg(unix)]
+    write: libc::c_int,
+}
+
+#[cfg(unix)]
+impl Waker {
+    /// Function to create the pipe, with both ends non-blocking so neither
+    /// waking nor clearing can ever hang
+    pub(crate) fn new() -> io::Result<Waker> {
+        let mut fds = [0; 2];
+        // Safe as pipe() only writes the two descriptors into the array, and
+        // fcntl() is only called on descriptors that were just created
+        unsafe {
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let waker = Waker {
+                read: fds[0],
+                write: fds[1],
+            };
+            for &fd in &fds {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                if flags == -1
+                    || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK)
+                        == -1
+                    || libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) == -1
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(waker)
+        }
+    }
+
+    /// Function to wake up the thread waiting on this, or make its next wait
+    /// return straight away if it is not waiting
+    pub(crate) fn wake(&self) {
+        wake_fd(self.write);
+    }
+
+    /// Returns the end of the pipe that `wake()` writes to, for signal
+    /// handlers
+    pub(crate) fn write_fd(&self) -> libc::c_int {
+        self.write
+    }
+
+    /// Function to empty the pipe after waking up, so the next wait blocks
+    fn clear(&self) {
+        let mut buf = [0u8; 64];
+        // Safe as the buffer is as long as the length passed in. The pipe
+        // is non-blocking, so this stops once it is empty
+        while unsafe {
+            libc::read(self.read, buf.as_mut_ptr() as *mut libc::c_void, 64)
+        } > 0
+        {}
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Waker {
+    fn drop(&mut self) {
+        // Safe as the descriptors belong to this and are not used afterwards
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
+
+/// Function to write a byte to the write end of a waker's pipe. A full pipe
+/// already wakes the thread, so errors are ignored. Only calls write(), so
+/// it may be used in a signal handler
+#[cfg(unix)]
+pub(crate) fn wake_fd(fd: libc::c_int) {
+    // Safe as the buffer is one byte long
+    unsafe {
+        libc::write(fd, [1u8].as_ptr() as *const libc::c_void, 1);
+    }
+}
+
+/// Function to block until at least one of the listeners has a connection
+/// to accept, the waker is woken or the timeout (if any) has passed.
+/// Returns the indexes of the listeners that have connections
+#[cfg(unix)]
+pub(crate) fn wait(
+    listeners: &[TcpListener],
+    waker: &Waker,
+    timeout: Option<Duration>,
+) -> io::Result<Vec<usize>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut fds = listeners
+        .iter()
+        .map(|listener| listener.as_raw_fd())
+        .chain(Some(waker.read))
+        .map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect::<Vec<libc::pollfd>>();
+    let timeout = match timeout {
+        Some(timeout) => {
+            timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int
+        }
+        None => -1,
+    };
+    // Safe as the length passed in is that of the array
+    let ready = unsafe {
+        libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout)
+    };
+    if ready == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    if fds[listeners.len()].revents != 0 {
+        waker.clear();
+    }
+    Ok(fds[..listeners.len()]
+        .iter()
+        .enumerate()
+        .filter(|(_, fd)| fd.revents != 0)
+        .map(|(i, _)| i)
+        .collect())
+}
+
+#[cfg(not(unix))]
+impl Waker {
+    pub(crate) fn new() -> io::Result<Waker> {
+        Ok(Waker {})
+    }
+
+    /// Function to wake up the waiting thread, which it does by itself here
+    pub(crate) fn wake(&self) {}
+}
+
+/// Function to wait for connections where readiness cannot be waited for,
+/// which checks every listener every so often instead
+#[cfg(not(unix))]
+pub(crate) fn wait(
+    listeners: &[TcpListener],
+    _waker: &Waker,
+    timeout: Option<Duration>,
+) -> io::Result<Vec<usize>> {
+    let interval = Duration::from_millis(50);
+    std::thread::sleep(
+        timeout.map_or(interval, |timeout| timeout.min(interval)),
+    );
+    Ok((0..listeners.len()).collect())
+}
@@ -0,0 +1,331 @@
+/* See LICENSE for license details */
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+
+use crate::form::upload_name;
+use crate::request::Request;
+use crate::response::StatusCode;
+use crate::sandbox::{DocumentRoot, SandboxError};
+
+/// The realm sent to clients that have to log in before publishing
+const REALM: &str = "rust_server";
+
+/// Everything that can go wrong while publishing or deleting a file
+#[derive(Debug)]
+pub enum PublishError {
+    /// No credentials, or the wrong ones, were sent
+    Unauthorized,
+    /// Publishing is turned off or the path is not writable
+    Forbidden,
+    /// The request path cannot be a file name
+    BadPath,
+    /// There is no file to delete
+    NotFound,
+    /// The path is a directory or its parent directory does not exist
+    Conflict,
+    /// The file is larger than allowed
+    TooLarge,
+    /// The file could not be written or removed
+    Storage(io::Error),
+    /// The connection could not be read from
+    Io(io::Error),
+}
+
+impl PublishError {
+    /// Returns the status to respond with, or None if the connection
+    /// should just be dropped
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            PublishError::Unauthorized => Some(StatusCode::Unauthorized),
+            PublishError::Forbidden => Some(StatusCode::Forbidden),
+            PublishError::BadPath => Some(StatusCode::BadRequest),
+            PublishError::NotFound => Some(StatusCode::NotFound),
+            PublishError::Conflict => Some(StatusCode::Conflict),
+            PublishError::TooLarge => Some(StatusCode::PayloadTooLarge),
+            PublishError::Storage(_) => Some(StatusCode::InternalServerError),
+            PublishError::Io(_) => None,
+        }
+    }
+
+    /// Returns the value of the WWW-Authenticate header that has to go with
+    /// the response, if there is one
+    pub fn challenge(&self) -> Option<String> {
+        match self {
+            PublishError::Unauthorized => {
+                Some(format!("Basic realm=\"{}\", charset=\"UTF-8\"", REALM))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PublishError::Unauthorized => write!(f, "not logged in"),
+            PublishError::Forbidden => write!(f, "path is not writable"),
+            PublishError::BadPath => write!(f, "malformed path"),
+            PublishError::NotFound => write!(f, "no such file"),
+            PublishError::Conflict => {
+                write!(f, "path is a directory or has no parent")
+            }
+            PublishError::TooLarge => write!(f, "file too large"),
+            PublishError::Storage(err) => write!(f, "storage: {}", err),
+            PublishError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<SandboxError> for PublishError {
+    fn from(err: SandboxError) -> PublishError {
+        match err {
+            SandboxError::BadPath => PublishError::BadPath,
+            SandboxError::Forbidden => PublishError::Forbidden,
+        }
+    }
+}
+
+/// Whether a PUT made a new file or replaced one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Published {
+    Created,
+    Replaced,
+}
+
+/// Decides who may publish files with PUT and DELETE, and where
+#[derive(Clone, Debug, Default)]
+pub struct Publisher {
+    writable: Vec<String>,
+    users: Vec<(String, String)>,
+}
+
+impl Publisher {
+    /// Function to create a publisher. `writable` holds the paths (relative
+    /// to the document root) under which files may be written and `users`
+    /// the (name, password) pairs allowed to write them. With no users
+    /// nothing can be published at all
+    pub fn new(writable: Vec<String>, users: Vec<(String, String)>) -> Self {
+        let writable = writable
+            .iter()
+            .map(|path| path.trim_matches('/').to_string())
+            .collect();
+        Publisher { writable, users }
+    }
+
+    /// Function to check that a request comes from a known user, using http
+    /// basic authentication
+    pub fn authorize(&self, request: &Request) -> Result<(), PublishError> {
+        if self.users.is_empty() {
+            return Err(PublishError::Forbidden);
+        }
+        let credentials = request
+            .header("Authorization")
+            .and_then(|value| {
+                let mut value = value.trim().splitn(2, ' ');
+                let scheme = value.next()?;
+                if !scheme.eq_ignore_ascii_case("basic") {
+                    return None;
+                }
+                base64_decode(value.next()?.trim())
+            })
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or(PublishError::Unauthorized)?;
+        let mut credentials = credentials.splitn(2, ':');
+        let name = credentials.next().unwrap_or("");
+        let password = credentials.next().unwrap_or("");
+        // Every user is checked in full, so how long it takes says nothing
+        // about how close the credentials were
+        let known = self.users.iter().fold(false, |known, (user, pass)| {
+            known | (same_secret(user, name) & same_secret(pass, password))
+        });
+        if known {
+            Ok(())
+        } else {
+            Err(PublishError::Unauthorized)
+        }
+    }
+
+    /// Returns if anything can be published at all
+    pub fn is_enabled(&self) -> bool {
+        !self.users.is_empty() && !self.writable.is_empty()
+    }
+
+    /// Returns if the path a request names could be published to by a
+    /// logged in user
+    pub fn is_writable(&self, root: &DocumentRoot, request: &Request) -> bool {
+        self.is_enabled() && self.writable_path(root, request).is_ok()
+    }
+
+    /// Function to turn the path of a request into the path of a file that
+    /// may be written
+    fn writable_path(
+        &self,
+        root: &DocumentRoot,
+        request: &Request,
+    ) -> Result<PathBuf, PublishError> {
+        let path = root.resolve(request.path())?;
+        let relative = path.strip_prefix(root.path()).unwrap_or(&path);
+        let relative = relative.to_string_lossy();
+        let allowed = self.writable.iter().any(|prefix| {
+            prefix.is_empty()
+                || relative == prefix.as_str()
+                || relative.starts_with(&format!("{}/", prefix))
+        });
+        if !allowed || relative.is_empty() {
+            return Err(PublishError::Forbidden);
+        }
+        Ok(path)
+    }
+
+    /// Function to check that a PUT request can be published before its
+    /// body is read, returning the path of the file it will write
+    pub fn check_put(
+        &self,
+        root: &DocumentRoot,
+        request: &Request,
+        max_size: u64,
+    ) -> Result<PathBuf, PublishError> {
+        let path = self.writable_path(root, request)?;
+        if path.is_dir() {
+            return Err(PublishError::Conflict);
+        }
+        match path.parent() {
+            Some(parent) if parent.is_dir() => {}
+            _ => return Err(PublishError::Conflict),
+        }
+        if request
+            .content_length()
+            .is_some_and(|length| length > max_size)
+        {
+            return Err(PublishError::TooLarge);
+        }
+        Ok(path)
+    }
+
+    /// Function to write the body of a PUT request to the file at `path`, as
+    /// returned by `check_put()`. The body goes to a temporary file next to
+    /// the target first, which is then renamed over it so nobody ever sees a
+    /// half written file. At most `max_size` bytes are accepted
+    pub fn put<R: BufRead>(
+        &self,
+        path: &Path,
+        request: &Request,
+        reader: &mut R,
+        max_size: u64,
+    ) -> Result<Published, PublishError> {
+        let parent = path.parent().ok_or(PublishError::Conflict)?;
+        let published = if path.exists() {
+            Published::Replaced
+        } else {
+            Published::Created
+        };
+
+        // The leading dot keeps the file from being served while it is
+        // being written
+        let temp = parent.join(format!(".{}.tmp", upload_name()));
+        let result =
+            write_body(request, reader, &temp, max_size).and_then(|_| {
+                fs::rename(&temp, path).map_err(PublishError::Storage)
+            });
+        if result.is_err() {
+            fs::remove_file(&temp).ok();
+        }
+        result.map(|_| published)
+    }
+
+    /// Function to remove the file a DELETE request names
+    pub fn delete(
+        &self,
+        root: &DocumentRoot,
+        request: &Request,
+    ) -> Result<(), PublishError> {
+        let path = self.writable_path(root, request)?;
+        let metadata =
+            fs::symlink_metadata(&path).map_err(|_| PublishError::NotFound)?;
+        if metadata.is_dir() {
+            return Err(PublishError::Conflict);
+        }
+        fs::remove_file(&path).map_err(PublishError::Storage)
+    }
+}
+
+/// Function to copy the body of a request into a new file
+fn write_body<R: BufRead>(
+    request: &Request,
+    reader: &mut R,
+    path: &Path,
+    max_size: u64,
+) -> Result<(), PublishError> {
+    let mut file = File::create(path).map_err(PublishError::Storage)?;
+    let mut body = request.body_reader(reader).take(max_size.saturating_add(1));
+    let mut buffer = [0; 8 * 1024];
+    let mut written = 0;
+    loop {
+        let read = match body.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(PublishError::Io(err)),
+        };
+        written += read as u64;
+        if written > max_size {
+            return Err(PublishError::TooLarge);
+        }
+        file.write_all(&buffer[..read])
+            .map_err(PublishError::Storage)?;
+    }
+    file.sync_all().map_err(PublishError::Storage)
+}
+
+/// Returns whether two secrets are the same, looking at every byte they
+/// share whether or not an earlier one differed, so the time it takes does
+/// not give away where they differ
+fn same_secret(expected: &str, given: &str) -> bool {
+    let difference = expected
+        .bytes()
+        .zip(given.bytes())
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    difference == 0 && expected.len() == given.len()
+}
+
+/// Function to decode standard base64, as used in basic authentication.
+/// Returns None if the input is not valid base64
+///
+/// # Examples
+///
+/// ```
+/// let decoded = server::base64_decode("dXNlcjpwYXNz").unwrap();
+/// assert_eq!(decoded, b"user:pass");
+///
+/// assert_eq!(server::base64_decode("not base64!"), None);
+/// ```
+pub fn base64_decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=');
+    let mut decoded = Vec::with_capacity(input.len() * 3 / 4);
+    let mut bits: u32 = 0;
+    let mut count = 0;
+    for byte in input.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        bits = (bits << 6) | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    // A single leftover character cannot make up a whole byte
+    if count >= 6 {
+        return None;
+    }
+    Some(decoded)
+}
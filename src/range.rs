@@ -0,0 +1,271 @@
+/* See LICENSE for license details */
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, prelude::*, SeekFrom};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http_date::{parse_http_date, whole_seconds};
+
+/// The most ranges one request may ask for. Requests for more get the whole
+/// file, as lots of tiny ranges cost more to send than the file itself
+pub const MAX_RANGES: usize = 16;
+
+/// Counts multipart responses, so no two of them share a boundary
+static BOUNDARY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// What a Range header asks for, once checked against the length of a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ranges {
+    /// The header is missing, malformed or asks for too much, so the whole
+    /// file should be sent as usual
+    Full,
+    /// The (start, end) byte positions to send, both inclusive, in the order
+    /// they were asked for
+    Partial(Vec<(u64, u64)>),
+    /// None of the ranges overlap the file
+    Unsatisfiable,
+}
+
+impl Ranges {
+    /// Function to parse the value of a Range header for a file that is
+    /// `length` bytes long. Only `bytes` ranges are understood
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use server::Ranges;
+    ///
+    /// assert_eq!(
+    ///     Ranges::parse("bytes=0-99", 1000),
+    ///     Ranges::Partial(vec![(0, 99)])
+    /// );
+    /// // Open ended and suffix ranges
+    /// assert_eq!(
+    ///     Ranges::parse("bytes=900-, -50", 1000),
+    ///     Ranges::Partial(vec![(900, 999), (950, 999)])
+    /// );
+    /// assert_eq!(Ranges::parse("bytes=1000-", 1000), Ranges::Unsatisfiable);
+    /// assert_eq!(Ranges::parse("lines=1-2", 1000), Ranges::Full);
+    /// ```
+    pub fn parse(header: &str, length: u64) -> Ranges {
+        let header = header.trim();
+        let specs = match header.find('=') {
+            Some(equals)
+                if header[..equals].trim().eq_ignore_ascii_case("bytes") =>
+            {
+                &header[equals + 1..]
+            }
+            _ => return Ranges::Full,
+        };
+
+        let mut ranges = Vec::new();
+        let mut count = 0;
+        for spec in specs.split(',').map(str::trim) {
+            if spec.is_empty() {
+                continue;
+            }
+            count += 1;
+            if count > MAX_RANGES {
+                return Ranges::Full;
+            }
+            let dash = match spec.find('-') {
+                Some(dash) => dash,
+                None => return Ranges::Full,
+            };
+            let (first, last) = (spec[..dash].trim(), spec[dash + 1..].trim());
+            let range = if first.is_empty() {
+                // A suffix range asks for the last so many bytes
+                let suffix = match parse_position(last) {
+                    Some(suffix) => suffix,
+                    None => return Ranges::Full,
+                };
+                if suffix == 0 || length == 0 {
+                    None
+                } else {
+                    Some((length.saturating_sub(suffix), length - 1))
+                }
+            } else {
+                let start = match parse_position(first) {
+                    Some(start) => start,
+                    None => return Ranges::Full,
+                };
+                let end = if last.is_empty() {
+                    u64::MAX
+                } else {
+                    match parse_position(last) {
+                        Some(end) if end >= start => end,
+                        _ => return Ranges::Full,
+                    }
+                };
+                if start >= length {
+                    None
+                } else {
+                    Some((start, end.min(length - 1)))
+                }
+            };
+            ranges.extend(range);
+        }
+
+        if count == 0 {
+            Ranges::Full
+        } else if ranges.is_empty() {
+            Ranges::Unsatisfiable
+        } else {
+            Ranges::Partial(ranges)
+        }
+    }
+}
+
+/// Function to check the value of an If-Range header. A range is only sent if
+/// the file has not changed since the client got the rest of it, as told by
+/// its entity tag or its modification date. Entity tags are only compared
+/// when the file has one
+pub fn if_range_matches(
+    value: &str,
+    modified: Option<SystemTime>,
+    etag: Option<&str>,
+) -> bool {
+    let value = value.trim();
+    if value.starts_with('"') || value.starts_with("W/") {
+        // Weak tags can never be used for ranges
+        return !value.starts_with("W/") && etag == Some(value);
+    }
+    match (parse_http_date(value), modified) {
+        (Some(date), Some(modified)) => date == whole_seconds(modified),
+        _ => false,
+    }
+}
+
+/// The body of a multipart/byteranges response, read from a file. Every part
+/// gets its own small head, so the whole length is known before anything is
+/// sent, and the file is only sought to a range once that range is reached
+pub(crate) struct MultipartRanges {
+    file: File,
+    boundary: String,
+    /// The head of every part still to come, with the range that follows it
+    parts: VecDeque<(String, u64, u64)>,
+    /// Text (a head or the closing boundary) still to be read
+    text: Vec<u8>,
+    position: usize,
+    /// Bytes of the current range still to be read from the file
+    remaining: u64,
+    finished: bool,
+    length: u64,
+}
+
+impl MultipartRanges {
+    /// Function to prepare the body for some ranges of a file that is
+    /// `file_length` bytes long and of type `content_type`
+    pub(crate) fn new(
+        file: File,
+        ranges: Vec<(u64, u64)>,
+        content_type: &str,
+        file_length: u64,
+    ) -> Self {
+        let boundary = boundary();
+        let parts: VecDeque<(String, u64, u64)> = ranges
+            .into_iter()
+            .map(|(start, end)| {
+                let head = format!(
+                    "\r\n--{}\r\nContent-Type: {}\r\n\
+                     Content-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, content_type, start, end, file_length
+                );
+                (head, start, end)
+            })
+            .collect();
+        let length = parts
+            .iter()
+            .map(|(head, start, end)| head.len() as u64 + end - start + 1)
+            .sum::<u64>()
+            + closing(&boundary).len() as u64;
+        MultipartRanges {
+            file,
+            boundary,
+            parts,
+            text: Vec::new(),
+            position: 0,
+            remaining: 0,
+            finished: false,
+            length,
+        }
+    }
+
+    /// Returns the Content-Type of the response
+    pub(crate) fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// Returns the length of the whole body
+    pub(crate) fn len(&self) -> u64 {
+        self.length
+    }
+}
+
+impl Read for MultipartRanges {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.text.len() {
+                let text = &self.text[self.position..];
+                let count = text.len().min(buf.len());
+                buf[..count].copy_from_slice(&text[..count]);
+                self.position += count;
+                return Ok(count);
+            }
+            if self.remaining > 0 {
+                let max = (buf.len() as u64).min(self.remaining) as usize;
+                let count = self.file.read(&mut buf[..max])?;
+                if count == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "file shrunk while being sent",
+                    ));
+                }
+                self.remaining -= count as u64;
+                return Ok(count);
+            }
+            let text = match self.parts.pop_front() {
+                Some((head, start, end)) => {
+                    self.file.seek(SeekFrom::Start(start))?;
+                    self.remaining = end - start + 1;
+                    head
+                }
+                None if !self.finished => {
+                    self.finished = true;
+                    closing(&self.boundary)
+                }
+                None => return Ok(0),
+            };
+            self.text = text.into_bytes();
+            self.position = 0;
+        }
+    }
+}
+
+/// Function to make the line that ends a multipart body
+fn closing(boundary: &str) -> String {
+    format!("\r\n--{}--\r\n", boundary)
+}
+
+/// Function to make a boundary for a multipart/byteranges response. It only
+/// has to be unlikely to show up in the file being sent
+fn boundary() -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "byteranges-{:x}{:08x}-{}",
+        time.as_secs(),
+        time.subsec_nanos(),
+        BOUNDARY_COUNT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Function to parse one of the numbers in a range
+fn parse_position(position: &str) -> Option<u64> {
+    if position.is_empty() || !position.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    position.parse().ok()
+}
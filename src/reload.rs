@@ -0,0 +1,96 @@
+/* See LICENSE for license details */
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::poll::Waker;
+
+/// How often a watched config file is looked at for changes
+pub(crate) const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A handle to ask a running server to read its config file again. The new
+/// config is checked before it is used, and if it is not valid the server
+/// carries on with the old one. Connections that are already open finish
+/// with the config they started with
+///
+/// # Examples
+///
+/// ```
+/// let server = server::Server::new(5);
+/// let reloader = server.reloader();
+///
+/// let handle = server.start_at("127.0.0.1:0", "config.txt");
+/// // After changing config.txt
+/// reloader.reload();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Reloader {
+    requested: Arc<AtomicBool>,
+    waker: Arc<Waker>,
+}
+
+impl Reloader {
+    /// Function to create a handle for the server that waits on `waker`
+    pub(crate) fn new(waker: Arc<Waker>) -> Reloader {
+        Reloader {
+            requested: Arc::new(AtomicBool::new(false)),
+            waker,
+        }
+    }
+
+    /// Function to ask for the config to be read again. The server does so
+    /// straight away, and asking again before it has only reads it once
+    pub fn reload(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+
+    /// Returns whether a reload was asked for since this was last called
+    pub(crate) fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::SeqCst)
+    }
+}
+
+/// Notices when a file has been changed, by its modification time and length
+pub(crate) struct Watcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+    checked: Instant,
+}
+
+impl Watcher {
+    /// Function to start watching a file as it is now
+    pub(crate) fn new(path: &Path) -> Watcher {
+        Watcher {
+            path: path.to_path_buf(),
+            stamp: stamp(path),
+            checked: Instant::now(),
+        }
+    }
+
+    /// Returns whether the file changed since this was last called. The file
+    /// is only looked at once every `WATCH_INTERVAL`, so this can be called
+    /// as often as needed
+    pub(crate) fn changed(&mut self) -> bool {
+        if self.checked.elapsed() < WATCH_INTERVAL {
+            return false;
+        }
+        self.checked = Instant::now();
+        let stamp = stamp(&self.path);
+        if stamp == self.stamp {
+            false
+        } else {
+            self.stamp = stamp;
+            true
+        }
+    }
+}
+
+/// Returns the modification time and length of a file, or None if it is not
+/// there
+fn stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,658 @@
+/* See LICENSE for license details */
+use std::error::Error;
+use std::fmt;
+use std::io::{self, prelude::*};
+
+use crate::headers::Headers;
+use crate::response::StatusCode;
+
+/// The longest request line that will be accepted
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// The most bytes the header block of a request may take up
+pub const MAX_HEADER_BYTES: usize = 64 * 1024;
+/// The most header lines a request may have
+pub const MAX_HEADERS: usize = 100;
+
+/// The method of a http request. Methods the server does not know about are
+/// kept in `Other` so that they can be told apart from malformed requests
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    Other(String),
+}
+
+impl Method {
+    /// Returns the method as it is written in a request
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+            Method::Other(method) => method.as_str(),
+        }
+    }
+}
+
+impl From<&str> for Method {
+    /// Methods are case sensitive, so `get` is not the same as `GET`
+    fn from(method: &str) -> Method {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "CONNECT" => Method::Connect,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "PATCH" => Method::Patch,
+            other => Method::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The http versions the server understands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    /// Returns the version as it is written in a request
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Everything that can go wrong while reading a request. Apart from `Closed`
+/// and `Io` each of these should be answered with the status code returned by
+/// `status()` before closing the connection
+#[derive(Debug)]
+pub enum ParseError {
+    /// The connection was closed before any part of a request was sent
+    Closed,
+    /// The connection could not be read from
+    Io(io::Error),
+    /// The request is not valid http
+    BadRequest(String),
+    /// The request line is longer than `MAX_REQUEST_LINE`
+    UriTooLong,
+    /// The headers are larger than `MAX_HEADER_BYTES` or `MAX_HEADERS`
+    HeadersTooLarge,
+    /// The body is larger than the limit passed in by the caller
+    PayloadTooLarge,
+    /// The request is for a http version other than 1.0 or 1.1
+    VersionNotSupported,
+    /// The request uses a transfer coding the server cannot decode
+    NotImplemented(String),
+}
+
+impl ParseError {
+    /// Returns the status to respond with, or None if the connection
+    /// should just be dropped
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ParseError::Closed | ParseError::Io(_) => None,
+            ParseError::BadRequest(_) => Some(StatusCode::BadRequest),
+            ParseError::UriTooLong => Some(StatusCode::UriTooLong),
+            ParseError::HeadersTooLarge => Some(StatusCode::HeadersTooLarge),
+            ParseError::PayloadTooLarge => Some(StatusCode::PayloadTooLarge),
+            ParseError::VersionNotSupported => {
+                Some(StatusCode::VersionNotSupported)
+            }
+            ParseError::NotImplemented(_) => Some(StatusCode::NotImplemented),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Closed => write!(f, "connection closed"),
+            ParseError::Io(err) => write!(f, "{}", err),
+            ParseError::BadRequest(reason) => {
+                write!(f, "bad request: {}", reason)
+            }
+            ParseError::UriTooLong => write!(f, "request line too long"),
+            ParseError::HeadersTooLarge => write!(f, "headers too large"),
+            ParseError::PayloadTooLarge => write!(f, "body too large"),
+            ParseError::VersionNotSupported => {
+                write!(f, "http version not supported")
+            }
+            ParseError::NotImplemented(coding) => {
+                write!(f, "transfer coding not implemented: {}", coding)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> ParseError {
+        match err.kind() {
+            io::ErrorKind::InvalidData => {
+                ParseError::BadRequest(err.to_string())
+            }
+            io::ErrorKind::UnexpectedEof => {
+                ParseError::BadRequest(String::from("incomplete body"))
+            }
+            _ => ParseError::Io(err),
+        }
+    }
+}
+
+/// How the length of the body of a request is worked out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Framing {
+    Length(u64),
+    Chunked,
+}
+
+/// A parsed http request
+#[derive(Clone, Debug)]
+pub struct Request {
+    method: Method,
+    target: String,
+    version: Version,
+    headers: Headers,
+    body: Vec<u8>,
+    framing: Framing,
+}
+
+impl Request {
+    /// Function to read a whole request (head and body) from a reader. The
+    /// body may be at most `max_body` bytes long
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut raw = "POST /form?x=1 HTTP/1.1\r\nHost: localhost\r\n\
+    ///                Content-Length: 5\r\n\r\nhello".as_bytes();
+    ///
+    /// let request = server::Request::read_from(&mut raw, 1024).unwrap();
+    /// assert_eq!(request.method(), &server::Method::Post);
+    /// assert_eq!(request.path(), "/form");
+    /// assert_eq!(request.query(), Some("x=1"));
+    /// assert_eq!(request.header("host"), Some("localhost"));
+    /// assert_eq!(request.body(), b"hello");
+    /// ```
+    ///
+    /// Malformed requests are reported instead of panicking
+    ///
+    /// ```
+    /// let mut raw = "GET\r\n\r\n".as_bytes();
+    ///
+    /// let err = server::Request::read_from(&mut raw, 1024).unwrap_err();
+    /// assert_eq!(err.status(), Some(server::StatusCode::BadRequest));
+    /// ```
+    pub fn read_from<R: BufRead>(
+        reader: &mut R,
+        max_body: u64,
+    ) -> Result<Request, ParseError> {
+        let mut request = Request::read_head(reader)?;
+        request.read_body(reader, max_body)?;
+        Ok(request)
+    }
+
+    /// Function to read the request line and headers of a request, leaving
+    /// the body unread in the reader
+    pub fn read_head<R: BufRead>(
+        reader: &mut R,
+    ) -> Result<Request, ParseError> {
+        // Empty lines before the request line are allowed and ignored
+        let mut line;
+        let mut skipped = 0;
+        loop {
+            line = match read_line(reader, MAX_REQUEST_LINE)? {
+                Line::Complete(line) => line,
+                Line::Eof => return Err(ParseError::Closed),
+                Line::Truncated => {
+                    return Err(bad_request("incomplete request line"))
+                }
+                Line::TooLong => return Err(ParseError::UriTooLong),
+            };
+            if !line.is_empty() {
+                break;
+            }
+            skipped += 1;
+            if skipped > 8 {
+                return Err(bad_request("no request line"));
+            }
+        }
+
+        let line = String::from_utf8(line)
+            .map_err(|_| bad_request("request line is not valid text"))?;
+        let parts = line.split(' ').collect::<Vec<&str>>();
+        if parts.len() != 3 {
+            return Err(bad_request("malformed request line"));
+        }
+        if !is_token(parts[0]) {
+            return Err(bad_request("malformed method"));
+        }
+        let method = Method::from(parts[0]);
+        let target = parts[1];
+        if target.is_empty()
+            || target.bytes().any(|byte| byte.is_ascii_control())
+        {
+            return Err(bad_request("malformed request target"));
+        }
+        let version = parse_version(parts[2])?;
+
+        let mut headers = Headers::new();
+        let mut budget = MAX_HEADER_BYTES;
+        loop {
+            let line = match read_line(reader, budget)? {
+                Line::Complete(line) => line,
+                Line::Eof | Line::Truncated => {
+                    return Err(bad_request("incomplete headers"))
+                }
+                Line::TooLong => return Err(ParseError::HeadersTooLarge),
+            };
+            if line.is_empty() {
+                break;
+            }
+            budget = budget.saturating_sub(line.len() + 2);
+            if headers.len() == MAX_HEADERS {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            // Folded headers are obsolete and must be rejected
+            if line[0] == b' ' || line[0] == b'\t' {
+                return Err(bad_request("folded header"));
+            }
+            let colon = line
+                .iter()
+                .position(|&byte| byte == b':')
+                .ok_or_else(|| bad_request("header without a colon"))?;
+            let name = String::from_utf8_lossy(&line[..colon]);
+            if !is_token(&name) {
+                return Err(bad_request("malformed header name"));
+            }
+            let value = String::from_utf8_lossy(&line[colon + 1..]);
+            headers
+                .append(&name, value.trim_matches(|c| c == ' ' || c == '\t'));
+        }
+
+        if version == Version::Http11 && !headers.contains("Host") {
+            return Err(bad_request("missing host header"));
+        }
+        let framing = parse_framing(&mut headers)?;
+
+        Ok(Request {
+            method,
+            target: target.to_string(),
+            version,
+            headers,
+            body: Vec::new(),
+            framing,
+        })
+    }
+
+    /// Function to read the body of a request whose head was read with
+    /// `read_head()`. Fails without reading anything if the Content-Length
+    /// is more than `max_body`
+    pub fn read_body<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        max_body: u64,
+    ) -> Result<(), ParseError> {
+        if let Framing::Length(length) = self.framing {
+            if length > max_body {
+                return Err(ParseError::PayloadTooLarge);
+            }
+        }
+        let mut body = Vec::new();
+        self.body_reader(reader)
+            .take(max_body.saturating_add(1))
+            .read_to_end(&mut body)?;
+        if body.len() as u64 > max_body {
+            return Err(ParseError::PayloadTooLarge);
+        }
+        self.body = body;
+        Ok(())
+    }
+
+    /// Returns a reader over the body of the request, decoding chunked bodies
+    /// and stopping at the end of the body so that the next request on the
+    /// connection is left untouched
+    pub fn body_reader<'a, R: BufRead>(
+        &self,
+        reader: &'a mut R,
+    ) -> BodyReader<'a, R> {
+        let state = match self.framing {
+            Framing::Length(length) => BodyState::Length(length),
+            Framing::Chunked => BodyState::Chunk(0),
+        };
+        BodyReader { reader, state }
+    }
+
+    /// Returns the declared length of the body, or None if it is chunked
+    pub fn content_length(&self) -> Option<u64> {
+        match self.framing {
+            Framing::Length(length) => Some(length),
+            Framing::Chunked => None,
+        }
+    }
+
+    /// Returns the method of the request
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Returns the request target exactly as it was sent
+    pub fn target(&self) -> &str {
+        self.target.as_str()
+    }
+
+    /// Returns the path part of the request target, without the query string.
+    /// Absolute targets like `http://host/path` are reduced to their path
+    pub fn path(&self) -> &str {
+        let mut target = self.target.as_str();
+        for scheme in &["http://", "https://"] {
+            if target.len() > scheme.len()
+                && target[..scheme.len()].eq_ignore_ascii_case(scheme)
+            {
+                let rest = &target[scheme.len()..];
+                target = rest.find('/').map_or("/", |start| &rest[start..]);
+            }
+        }
+        match target.find('?') {
+            Some(end) => &target[..end],
+            None => target,
+        }
+    }
+
+    /// Returns the query string of the request target, if there is one
+    pub fn query(&self) -> Option<&str> {
+        self.target.find('?').map(|start| &self.target[start + 1..])
+    }
+
+    /// Returns the http version of the request
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Returns if the client wants the connection kept open after this
+    /// request. HTTP/1.1 connections stay open unless `Connection: close` is
+    /// sent, HTTP/1.0 ones only if `Connection: keep-alive` is sent
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.headers.has_token("Connection", "close"),
+            Version::Http10 => {
+                self.headers.has_token("Connection", "keep-alive")
+            }
+        }
+    }
+
+    /// Returns the value of the first header with the given name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns all the headers of the request
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Returns all the headers of the request, to change them. How the body
+    /// is read was decided when the head was, so changing Content-Length or
+    /// Transfer-Encoding here has no effect on that
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// Function to change the target of the request, e.g. to rewrite its
+    /// path before it is handled
+    pub fn set_target(&mut self, target: &str) {
+        self.target = target.to_string();
+    }
+
+    /// Returns the body of the request. Empty if the request had no body or
+    /// it has not been read yet
+    pub fn body(&self) -> &[u8] {
+        self.body.as_slice()
+    }
+}
+
+impl fmt::Display for Request {
+    /// Writes the head of the request the way it was sent
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}\r\n{}",
+            self.method, self.target, self.version, self.headers
+        )
+    }
+}
+
+/// The part of the body a `BodyReader` is in
+#[derive(Debug)]
+enum BodyState {
+    /// Bytes left in a body with a Content-Length
+    Length(u64),
+    /// Bytes left in the current chunk of a chunked body
+    Chunk(u64),
+    /// The last chunk and trailers have been read
+    Done,
+}
+
+/// A reader over the body of a request. Created by `Request::body_reader()`
+#[derive(Debug)]
+pub struct BodyReader<'a, R> {
+    reader: &'a mut R,
+    state: BodyState,
+}
+
+impl<R: BufRead> BodyReader<'_, R> {
+    /// Reads the size line of the next chunk, and the trailers if it was the
+    /// last chunk
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let line = expect_line(self.reader)?;
+        let line = String::from_utf8_lossy(&line);
+        // Chunk extensions come after a ';' and are ignored
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = u64::from_str_radix(size, 16)
+            .map_err(|_| invalid_data("malformed chunk size"))?;
+        if size == 0 {
+            while !expect_line(self.reader)?.is_empty() {}
+            self.state = BodyState::Done;
+        } else {
+            self.state = BodyState::Chunk(size);
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for BodyReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let BodyState::Chunk(0) = self.state {
+            self.next_chunk()?;
+        }
+        let left = match self.state {
+            BodyState::Length(left) | BodyState::Chunk(left) => left,
+            BodyState::Done => 0,
+        };
+        if left == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let max = left.min(buf.len() as u64) as usize;
+        let read = self.reader.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "body ended early",
+            ));
+        }
+        let left = left - read as u64;
+        match self.state {
+            BodyState::Length(_) => self.state = BodyState::Length(left),
+            _ => {
+                self.state = BodyState::Chunk(left);
+                // Every chunk is followed by an empty line
+                if left == 0 && !expect_line(self.reader)?.is_empty() {
+                    return Err(invalid_data("chunk longer than its size"));
+                }
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// The result of reading a single line
+enum Line {
+    /// A whole line, without the line ending
+    Complete(Vec<u8>),
+    /// The reader was already at its end
+    Eof,
+    /// The reader ended in the middle of a line
+    Truncated,
+    /// The line was longer than allowed
+    TooLong,
+}
+
+/// Reads a line ending in `\r\n` (or just `\n`) of at most `limit` bytes
+fn read_line<R: BufRead>(reader: &mut R, limit: usize) -> io::Result<Line> {
+    let mut line = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(limit as u64 + 2)
+        .read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(Line::Eof);
+    }
+    if line.last() != Some(&b'\n') {
+        if line.len() > limit {
+            return Ok(Line::TooLong);
+        }
+        return Ok(Line::Truncated);
+    }
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.len() > limit {
+        return Ok(Line::TooLong);
+    }
+    Ok(Line::Complete(line))
+}
+
+/// Reads a line inside a chunked body, where anything but a whole line is an
+/// error
+fn expect_line<R: BufRead>(reader: &mut R) -> io::Result<Vec<u8>> {
+    match read_line(reader, MAX_REQUEST_LINE)? {
+        Line::Complete(line) => Ok(line),
+        Line::TooLong => Err(invalid_data("chunk line too long")),
+        Line::Eof | Line::Truncated => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "body ended early",
+        )),
+    }
+}
+
+/// Parses the version at the end of a request line
+fn parse_version(version: &str) -> Result<Version, ParseError> {
+    match version {
+        "HTTP/1.1" => Ok(Version::Http11),
+        "HTTP/1.0" => Ok(Version::Http10),
+        _ => {
+            let bytes = version.as_bytes();
+            if bytes.len() == 8
+                && version.starts_with("HTTP/")
+                && bytes[5].is_ascii_digit()
+                && bytes[6] == b'.'
+                && bytes[7].is_ascii_digit()
+            {
+                Err(ParseError::VersionNotSupported)
+            } else {
+                Err(bad_request("malformed http version"))
+            }
+        }
+    }
+}
+
+/// Works out how the body is sent. Transfer-Encoding wins over
+/// Content-Length, which is removed so nothing else can trust it
+fn parse_framing(headers: &mut Headers) -> Result<Framing, ParseError> {
+    if headers.contains("Transfer-Encoding") {
+        let codings = headers
+            .get_all("Transfer-Encoding")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|coding| coding.trim().to_ascii_lowercase())
+            .filter(|coding| !coding.is_empty())
+            .collect::<Vec<String>>();
+        // Without chunked last there is no way to tell where the body ends
+        if codings.last().map(String::as_str) != Some("chunked") {
+            return Err(bad_request("body is not chunked"));
+        }
+        if codings.len() > 1 {
+            return Err(ParseError::NotImplemented(codings.join(", ")));
+        }
+        headers.remove("Content-Length");
+        return Ok(Framing::Chunked);
+    }
+
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        for part in value.split(',') {
+            let part = part.trim();
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(bad_request("malformed content length"));
+            }
+            let part = part
+                .parse::<u64>()
+                .map_err(|_| bad_request("malformed content length"))?;
+            if length.is_some() && length != Some(part) {
+                return Err(bad_request("conflicting content lengths"));
+            }
+            length = Some(part);
+        }
+    }
+    Ok(Framing::Length(length.unwrap_or(0)))
+}
+
+/// Checks if a string is a http token (what methods and header names are)
+fn is_token(token: &str) -> bool {
+    !token.is_empty()
+        && token.bytes().all(|byte| {
+            byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+        })
+}
+
+fn bad_request(reason: &str) -> ParseError {
+    ParseError::BadRequest(reason.to_string())
+}
+
+fn invalid_data(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
@@ -0,0 +1,460 @@
+/* See LICENSE for license details */
+use std::fmt;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::time::SystemTime;
+
+use crate::headers::Headers;
+use crate::http_date::format_http_date;
+use crate::request::{Method, Request, Version};
+
+/// Sent in the Server header of every response
+pub const SERVER: &str = concat!("rust_server/", env!("CARGO_PKG_VERSION"));
+
+/// How many bytes of a body of unknown length are collected before they are
+/// sent as one chunk
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Declares the status codes along with their reason phrases
+macro_rules! status_codes {
+    ($($name:ident = $code:expr, $reason:expr;)*) => {
+        /// The status codes a response can have
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum StatusCode {
+            $($name,)*
+        }
+
+        impl StatusCode {
+            /// Returns the number of the status, e.g. 404
+            pub fn code(self) -> u16 {
+                match self {
+                    $(StatusCode::$name => $code,)*
+                }
+            }
+
+            /// Returns the reason phrase of the status, e.g. `Not Found`
+            pub fn reason(self) -> &'static str {
+                match self {
+                    $(StatusCode::$name => $reason,)*
+                }
+            }
+
+            /// Function to get a status from its number. Returns None for
+            /// numbers that are not known
+            pub fn from_code(code: u16) -> Option<StatusCode> {
+                match code {
+                    $($code => Some(StatusCode::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    Continue = 100, "Continue";
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NoContent = 204, "No Content";
+    PartialContent = 206, "Partial Content";
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    PayloadTooLarge = 413, "Payload Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    TooManyRequests = 429, "Too Many Requests";
+    HeadersTooLarge = 431, "Request Header Fields Too Large";
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    VersionNotSupported = 505, "HTTP Version Not Supported";
+}
+
+impl StatusCode {
+    /// Returns if the status is a client or server error
+    pub fn is_error(self) -> bool {
+        self.code() >= 400
+    }
+
+    /// Returns if responses with the status may have a body. Informational
+    /// responses, 204 No Content and 304 Not Modified never do
+    pub fn has_body(self) -> bool {
+        let code = self.code();
+        code >= 200 && code != 204 && code != 304
+    }
+}
+
+/// Shows the status as it appears in the status line, e.g. `404 Not Found`
+///
+/// # Examples
+///
+/// ```
+/// use server::StatusCode;
+///
+/// assert_eq!(StatusCode::NotFound.to_string(), "404 Not Found");
+/// assert_eq!(StatusCode::from_code(206), Some(StatusCode::PartialContent));
+/// ```
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+/// The body of a response
+pub enum Body {
+    Empty,
+    /// Bytes held in memory
+    Bytes(Vec<u8>),
+    /// The next `length` bytes of a file, which is copied to the connection
+    /// a piece at a time rather than read into memory
+    File(File, u64),
+    /// Bytes read from anything else. Without a length the body is sent in
+    /// the chunked transfer coding
+    Stream(Box<dyn Read + Send>, Option<u64>),
+}
+
+impl Body {
+    /// Returns the length of the body, if it is known before it is sent
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File(_, length) => Some(*length),
+            Body::Stream(_, length) => *length,
+        }
+    }
+
+    /// Returns if the body is known to be empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Bytes(bytes) => write!(f, "Bytes({} bytes)", bytes.len()),
+            Body::File(_, length) => write!(f, "File({} bytes)", length),
+            Body::Stream(_, Some(length)) => {
+                write!(f, "Stream({} bytes)", length)
+            }
+            Body::Stream(_, None) => write!(f, "Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Body {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+/// A response to a request, made up of a status, headers and a body. Date,
+/// Server, Content-Length (or Transfer-Encoding) and Connection are filled in
+/// when it is written, unless they have been set already
+///
+/// # Examples
+///
+/// ```
+/// use server::{Response, StatusCode};
+///
+/// let response = Response::new(StatusCode::Ok)
+///     .with_header("Content-Type", "text/plain")
+///     .with_body("hello");
+///
+/// let mut written = Vec::new();
+/// response.write_to(&mut written, None, false).unwrap();
+///
+/// let written = String::from_utf8(written).unwrap();
+/// assert!(written.starts_with("HTTP/1.1 200 OK\r\n"));
+/// assert!(written.contains("Content-Length: 5\r\n"));
+/// assert!(written.contains("Connection: close\r\n"));
+/// assert!(written.ends_with("\r\n\r\nhello"));
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    status: StatusCode,
+    headers: Headers,
+    body: Body,
+}
+
+impl Response {
+    /// Function to create a response with a status, no headers and no body
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Empty,
+        }
+    }
+
+    /// Returns the status of the response
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Function to change the status of the response
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.status = status;
+    }
+
+    /// Returns the headers of the response
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// Returns the headers of the response, to change them
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// Returns the value of a header of the response
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns the body of the response
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    /// Function to change the body of the response
+    pub fn set_body<B: Into<Body>>(&mut self, body: B) {
+        self.body = body.into();
+    }
+
+    /// Function to add a header to the response
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Function to set the body of the response
+    pub fn with_body<B: Into<Body>>(mut self, body: B) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Function to send the next `length` bytes of a file as the body
+    pub fn with_file(mut self, file: File, length: u64) -> Response {
+        self.body = Body::File(file, length);
+        self
+    }
+
+    /// Function to send what is read from `reader` as the body. Without a
+    /// `length` the body is sent in the chunked transfer coding
+    pub fn with_stream<R>(mut self, reader: R, length: Option<u64>) -> Response
+    where
+        R: Read + Send + 'static,
+    {
+        self.body = Body::Stream(Box::new(reader), length);
+        self
+    }
+
+    /// Function to write the response to a connection. `request` is the
+    /// request it answers (None if it could not be read), which decides if
+    /// the body is left out (for HEAD) and if it can be chunked (not for
+    /// HTTP/1.0). Returns if the connection can still be used afterwards,
+    /// which is only the case if `keep_alive` is set and the response did
+    /// not have to be ended by closing it
+    pub fn write_to<W: Write>(
+        mut self,
+        writer: &mut W,
+        request: Option<&Request>,
+        keep_alive: bool,
+    ) -> io::Result<bool> {
+        let keep_alive = self.prepare(request, keep_alive);
+        let head_only =
+            request.is_some_and(|request| request.method() == &Method::Head);
+        self.write_prepared(writer, head_only)?;
+        Ok(keep_alive)
+    }
+
+    /// Function to fill in the headers every response has. Returns if the
+    /// connection can still be used afterwards
+    pub(crate) fn prepare(
+        &mut self,
+        request: Option<&Request>,
+        keep_alive: bool,
+    ) -> bool {
+        let can_chunk =
+            request.is_none_or(|request| request.version() == Version::Http11);
+        let mut keep_alive =
+            keep_alive && !self.headers.has_token("Connection", "close");
+
+        if !self.headers.contains("Date") {
+            self.headers
+                .insert("Date", &format_http_date(SystemTime::now()));
+        }
+        if !self.headers.contains("Server") {
+            self.headers.insert("Server", SERVER);
+        }
+        if self.status.has_body() {
+            match self.body.len() {
+                Some(length) => {
+                    self.headers.remove("Transfer-Encoding");
+                    self.headers.insert("Content-Length", &length.to_string());
+                }
+                None if can_chunk => {
+                    self.headers.remove("Content-Length");
+                    self.headers.insert("Transfer-Encoding", "chunked");
+                }
+                // Old clients can only tell where the body ends when the
+                // connection is closed
+                None => {
+                    self.headers.remove("Content-Length");
+                    self.headers.remove("Transfer-Encoding");
+                    keep_alive = false;
+                }
+            }
+        } else {
+            self.body = Body::Empty;
+        }
+        self.headers.insert(
+            "Connection",
+            if keep_alive { "keep-alive" } else { "close" },
+        );
+        keep_alive
+    }
+
+    /// Returns the status line and headers of the response as they are sent
+    pub fn head(&self) -> String {
+        format!("HTTP/1.1 {}\r\n{}\r\n", self.status, self.headers)
+    }
+
+    /// Function to write a response whose headers have been filled in
+    pub(crate) fn write_prepared<W: Write>(
+        self,
+        writer: &mut W,
+        head_only: bool,
+    ) -> io::Result<()> {
+        writer.write_all(self.head().as_bytes())?;
+        if head_only {
+            return writer.flush();
+        }
+        let chunked = self.headers.has_token("Transfer-Encoding", "chunked");
+        match self.body {
+            Body::Empty => {}
+            Body::Bytes(bytes) => writer.write_all(&bytes)?,
+            Body::File(file, length) => copy_exactly(file, length, writer)?,
+            Body::Stream(reader, Some(length)) => {
+                copy_exactly(reader, length, writer)?
+            }
+            Body::Stream(mut reader, None) if chunked => {
+                let mut chunked = ChunkedWriter::new(&mut *writer);
+                io::copy(&mut reader, &mut chunked)?;
+                chunked.finish()?;
+            }
+            Body::Stream(mut reader, None) => {
+                io::copy(&mut reader, writer)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Function to copy exactly `length` bytes to a writer
+fn copy_exactly<R: Read, W: Write>(
+    reader: R,
+    length: u64,
+    writer: &mut W,
+) -> io::Result<()> {
+    let sent = io::copy(&mut reader.take(length), writer)?;
+    // If a file shrunk while it was being sent the client is still waiting
+    // for the rest, so the connection has to be closed
+    if sent < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "body shrunk while being sent",
+        ));
+    }
+    Ok(())
+}
+
+/// Writes everything given to it in the chunked transfer coding, collecting
+/// small writes into bigger chunks
+struct ChunkedWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    fn new(inner: W) -> Self {
+        ChunkedWriter {
+            inner,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    /// Function to send what has been collected as one chunk
+    fn send_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        write!(self.inner, "{:x}\r\n", self.buffer.len())?;
+        self.buffer.extend_from_slice(b"\r\n");
+        self.inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Function to send the last chunk and the empty one that ends the body
+    fn finish(mut self) -> io::Result<()> {
+        self.send_chunk()?;
+        self.inner.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_chunk()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_chunk()?;
+        self.inner.flush()
+    }
+}
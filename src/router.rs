@@ -0,0 +1,320 @@
+/* See LICENSE for license details */
+use std::fmt;
+use std::sync::Arc;
+
+use crate::middleware::{self, Middleware};
+use crate::request::{Method, Request};
+use crate::response::{Response, StatusCode};
+use crate::sandbox::percent_decode;
+
+/// What answers the requests sent to a route
+pub(crate) type Handler = dyn Fn(&Request, &Params) -> Response + Send + Sync;
+
+/// The parts of a path that were matched by the parameters of a route, e.g.
+/// `id` in `/users/:id`. Values are percent decoded
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+    wildcard: Option<String>,
+}
+
+impl Params {
+    /// Returns the value of a named parameter, including a named wildcard
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the rest of the path matched by the wildcard of the route, if
+    /// it has one. It may be empty, and keeps the slashes between segments
+    pub fn wildcard(&self) -> Option<&str> {
+        self.wildcard.as_deref()
+    }
+
+    /// Returns an iterator over the (name, value) pairs of the parameters
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+/// One segment of a route pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    /// Has to be exactly this text
+    Literal(String),
+    /// `:name`, which matches any one segment
+    Param(String),
+    /// `*` or `*name` at the end, which matches whatever is left
+    Wildcard(Option<String>),
+}
+
+/// A route, made of a method, a pattern and the handler for both, along
+/// with the middleware wrapped around only this route
+#[derive(Clone)]
+pub struct Route {
+    method: Method,
+    pattern: String,
+    segments: Vec<Segment>,
+    handler: Arc<Handler>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Route {
+    /// Function to wrap middleware around the handler of the route. It runs
+    /// inside any middleware of the server, and in the order it was added.
+    /// Unlike the middleware of the server it sees the body of the request
+    pub fn wrap<M: Middleware + 'static>(
+        &mut self,
+        middleware: M,
+    ) -> &mut Route {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.pattern)
+    }
+}
+
+/// Sends requests to handlers by their method and path. Patterns are paths
+/// in which a segment can be `:name` to match any one segment, and the last
+/// segment can be `*` (or `*name`) to match all of the rest. Routes are tried
+/// in the order they were added, and GET routes also answer HEAD requests
+///
+/// # Examples
+///
+/// ```
+/// use server::{Method, Params, Request, Response, Router, StatusCode};
+///
+/// let mut router = Router::new();
+/// router.route(Method::Get, "/users/:id", |_: &Request, params: &Params| {
+///     let id = params.get("id").unwrap();
+///     Response::new(StatusCode::Ok).with_body(format!("user {}", id))
+/// });
+///
+/// let mut raw = "GET /users/42 HTTP/1.1\r\nHost: x\r\n\r\n".as_bytes();
+/// let mut request = Request::read_from(&mut raw, 0).unwrap();
+/// let response = router.handle(&mut request).unwrap();
+/// assert_eq!(response.status(), StatusCode::Ok);
+///
+/// // Known paths with other methods are not allowed
+/// let mut raw = "DELETE /users/42 HTTP/1.1\r\nHost: x\r\n\r\n".as_bytes();
+/// let mut request = Request::read_from(&mut raw, 0).unwrap();
+/// let response = router.handle(&mut request).unwrap();
+/// assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+/// assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+/// assert_eq!(router.methods(), vec![Method::Get, Method::Head]);
+///
+/// // Anything else is left to someone else
+/// let mut raw = "GET /index.html HTTP/1.1\r\nHost: x\r\n\r\n".as_bytes();
+/// let mut request = Request::read_from(&mut raw, 0).unwrap();
+/// assert!(router.handle(&mut request).is_none());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    /// Function to create a router without any routes
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Function to add a route. Requests with the method whose path matches
+    /// the pattern are answered by the handler. Returns the route, so
+    /// middleware can be wrapped around it
+    ///
+    /// # Panics
+    ///
+    /// If the pattern does not start with `/`, has a parameter without a
+    /// name or has a wildcard anywhere but at the end
+    pub fn route<F>(
+        &mut self,
+        method: Method,
+        pattern: &str,
+        handler: F,
+    ) -> &mut Route
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            pattern: pattern.to_string(),
+            segments: parse_pattern(pattern),
+            handler: Arc::new(handler),
+            middleware: Vec::new(),
+        });
+        let last = self.routes.len() - 1;
+        &mut self.routes[last]
+    }
+
+    /// Returns if there are no routes at all
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Returns if any route matches a path, whatever its method
+    pub fn matches(&self, path: &str) -> bool {
+        self.routes
+            .iter()
+            .any(|route| match_segments(&route.segments, path).is_some())
+    }
+
+    /// Function to find the route for a request, along with the parameters
+    /// of its path
+    fn find(&self, method: &Method, path: &str) -> Option<(&Route, Params)> {
+        self.routes
+            .iter()
+            .filter(|route| {
+                route.method == *method
+                    || (route.method == Method::Get && *method == Method::Head)
+            })
+            .find_map(|route| {
+                let params = match_segments(&route.segments, path)?;
+                Some((route, params))
+            })
+    }
+
+    /// Returns the methods that have a route for a path
+    pub fn allowed(&self, path: &str) -> Vec<Method> {
+        self.methods_of(|route| match_segments(&route.segments, path).is_some())
+    }
+
+    /// Returns the methods that have a route for any path
+    pub fn methods(&self) -> Vec<Method> {
+        self.methods_of(|_| true)
+    }
+
+    /// Function to collect the methods of the routes picked by `filter`,
+    /// each listed once, with HEAD along with GET
+    fn methods_of<F: Fn(&Route) -> bool>(&self, filter: F) -> Vec<Method> {
+        let mut allowed = Vec::new();
+        for route in &self.routes {
+            if !filter(route) {
+                continue;
+            }
+            let mut methods = vec![route.method.clone()];
+            if route.method == Method::Get {
+                methods.push(Method::Head);
+            }
+            for method in methods {
+                if !allowed.contains(&method) {
+                    allowed.push(method);
+                }
+            }
+        }
+        allowed
+    }
+
+    /// Function to answer a request with the route it matches, through the
+    /// middleware of the route. Paths that have routes, but not for the
+    /// method of the request, are answered with 405 Method Not Allowed, or
+    /// the list of methods for OPTIONS. Returns None if no route matches the
+    /// path at all
+    pub fn handle(&self, request: &mut Request) -> Option<Response> {
+        let path = request.path().to_string();
+        if let Some((route, params)) = self.find(request.method(), &path) {
+            let handler = &route.handler;
+            // Handlers cannot fail, so neither can the chain
+            return middleware::run(&route.middleware, request, |request| {
+                Ok(handler(request, &params))
+            })
+            .ok();
+        }
+        let allowed = self.allowed(&path);
+        if allowed.is_empty() {
+            return None;
+        }
+        let allow = allowed
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<&str>>()
+            .join(", ");
+        let status = if request.method() == &Method::Options {
+            StatusCode::NoContent
+        } else {
+            StatusCode::MethodNotAllowed
+        };
+        Some(Response::new(status).with_header("Allow", &allow))
+    }
+}
+
+/// Function to split a route pattern into its segments
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    assert!(
+        pattern.starts_with('/'),
+        "route pattern {} does not start with /",
+        pattern
+    );
+    let parts: Vec<&str> = pattern[1..].split('/').collect();
+    let last = parts.len() - 1;
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            if let Some(name) = part.strip_prefix(':') {
+                assert!(!name.is_empty(), "unnamed parameter in {}", pattern);
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                assert!(i == last, "wildcard before the end of {}", pattern);
+                if name.is_empty() {
+                    Segment::Wildcard(None)
+                } else {
+                    Segment::Wildcard(Some(name.to_string()))
+                }
+            } else {
+                Segment::Literal(part.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Function to match a path against the segments of a pattern. Returns the
+/// parameters if it matches
+fn match_segments(segments: &[Segment], path: &str) -> Option<Params> {
+    let path = path.strip_prefix('/')?;
+    let mut parts = path.split('/');
+    let mut params = Params::default();
+    for segment in segments {
+        match segment {
+            Segment::Wildcard(name) => {
+                let rest = parts.collect::<Vec<&str>>().join("/");
+                let rest = decode(&rest)?;
+                if let Some(name) = name {
+                    params.values.push((name.clone(), rest.clone()));
+                }
+                params.wildcard = Some(rest);
+                return Some(params);
+            }
+            Segment::Literal(literal) => {
+                if decode(parts.next()?)? != *literal {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                let part = parts.next()?;
+                if part.is_empty() {
+                    return None;
+                }
+                params.values.push((name.clone(), decode(part)?));
+            }
+        }
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(params),
+    }
+}
+
+/// Function to percent decode part of a path. Returns None if it is not
+/// valid UTF-8 once decoded
+fn decode(part: &str) -> Option<String> {
+    String::from_utf8(percent_decode(part)?).ok()
+}
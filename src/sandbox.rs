@@ -0,0 +1,191 @@
+/* See LICENSE for license details */
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::response::StatusCode;
+
+/// Files that are never served no matter where they are, on top of anything
+/// added with `deny:` in the config file
+pub const DEFAULT_DENY: [&str; 2] = ["config.txt", "ips.txt"];
+
+/// Why a request path could not be turned into a file path
+#[derive(Debug, PartialEq, Eq)]
+pub enum SandboxError {
+    /// The path has a broken percent escape or decodes to something that
+    /// cannot be a file name
+    BadPath,
+    /// The path leaves the document root or names a denied file
+    Forbidden,
+}
+
+impl SandboxError {
+    /// Returns the status to respond with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            SandboxError::BadPath => StatusCode::BadRequest,
+            SandboxError::Forbidden => StatusCode::Forbidden,
+        }
+    }
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SandboxError::BadPath => write!(f, "malformed path"),
+            SandboxError::Forbidden => write!(f, "forbidden path"),
+        }
+    }
+}
+
+/// The directory files are served from, along with the names of files in it
+/// that must never be served
+#[derive(Clone, Debug)]
+pub struct DocumentRoot {
+    root: PathBuf,
+    deny: Vec<String>,
+}
+
+impl DocumentRoot {
+    /// Function to create a document root from a directory and a list of
+    /// denied names. A denied name without a '/' matches a file or directory
+    /// of that name anywhere under the root, one with a '/' only matches that
+    /// path relative to the root
+    ///
+    /// # Errors
+    ///
+    /// If the directory does not exist or cannot be read
+    pub fn new(root: &str, deny: Vec<String>) -> io::Result<DocumentRoot> {
+        let root = Path::new(root).canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::other(format!(
+                "{} is not a directory",
+                root.display()
+            )));
+        }
+        let deny = deny
+            .into_iter()
+            .map(|name| name.trim_matches('/').to_string())
+            .filter(|name| !name.is_empty())
+            .collect();
+        Ok(DocumentRoot { root, deny })
+    }
+
+    /// Returns the (canonical) directory files are served from
+    pub fn path(&self) -> &Path {
+        self.root.as_path()
+    }
+
+    /// Function to turn the path of a request into the path of a file under
+    /// the root. The path is percent decoded and normalized first, and must
+    /// not go above the root, name a dotfile or a denied file, or lead
+    /// outside the root through a symlink. The file does not have to exist
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use server::{DocumentRoot, SandboxError};
+    ///
+    /// let root = DocumentRoot::new(".", vec![]).unwrap();
+    ///
+    /// let path = root.resolve("/styles/%73tyles.css").unwrap();
+    /// assert_eq!(path, root.path().join("styles/styles.css"));
+    ///
+    /// let err = root.resolve("/../../etc/passwd").unwrap_err();
+    /// assert_eq!(err, SandboxError::Forbidden);
+    ///
+    /// let err = root.resolve("/.git/config").unwrap_err();
+    /// assert_eq!(err, SandboxError::Forbidden);
+    /// ```
+    pub fn resolve(&self, request_path: &str) -> Result<PathBuf, SandboxError> {
+        let decoded =
+            percent_decode(request_path).ok_or(SandboxError::BadPath)?;
+        let decoded =
+            String::from_utf8(decoded).map_err(|_| SandboxError::BadPath)?;
+        if decoded.contains('\0') {
+            return Err(SandboxError::BadPath);
+        }
+
+        let mut segments: Vec<&str> = Vec::new();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    // Going above the root is never allowed, even if the path
+                    // would come back down into it
+                    segments.pop().ok_or(SandboxError::Forbidden)?;
+                }
+                _ => segments.push(segment),
+            }
+        }
+        if segments.iter().any(|segment| segment.starts_with('.')) {
+            return Err(SandboxError::Forbidden);
+        }
+        let relative = segments.join("/");
+        if self.is_denied(&segments, &relative) {
+            return Err(SandboxError::Forbidden);
+        }
+
+        let path = self.root.join(&relative);
+        if !self.is_inside(&path) {
+            return Err(SandboxError::Forbidden);
+        }
+        Ok(path)
+    }
+
+    /// Checks a normalized path against the deny list
+    fn is_denied(&self, segments: &[&str], relative: &str) -> bool {
+        self.deny.iter().any(|name| {
+            if name.contains('/') {
+                relative == name || relative.starts_with(&format!("{}/", name))
+            } else {
+                segments.contains(&name.as_str())
+            }
+        })
+    }
+
+    /// Checks that the part of `path` that exists is still inside the root
+    /// once every symlink in it has been followed
+    fn is_inside(&self, path: &Path) -> bool {
+        let mut existing = Some(path);
+        while let Some(current) = existing {
+            if let Ok(canonical) = current.canonicalize() {
+                return canonical.starts_with(&self.root);
+            }
+            existing = current.parent();
+        }
+        false
+    }
+}
+
+/// Function to decode `%XX` escapes in a string. Returns None if an escape is
+/// cut short or is not hexadecimal
+///
+/// # Examples
+///
+/// ```
+/// let decoded = server::percent_decode("/a%20file%2Etxt").unwrap();
+/// assert_eq!(decoded, b"/a file.txt");
+///
+/// assert_eq!(server::percent_decode("/broken%2"), None);
+/// ```
+pub fn percent_decode(input: &str) -> Option<Vec<u8>> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(decoded)
+}
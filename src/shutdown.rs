@@ -0,0 +1,348 @@
+/* See LICENSE for license details */
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::{Shutdown, TcpStream};
+use std::panic;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::poll::Waker;
+
+/// How a graceful shutdown went: requests that were being handled or waiting
+/// for a worker when it started are either drained, meaning they were
+/// answered, or aborted, meaning their connection was closed without an
+/// answer, by the client or when the deadline passed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Requests that were answered
+    pub drained: usize,
+    /// Requests whose connection was closed before they were answered
+    pub aborted: usize,
+}
+
+impl fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} request{} drained, {} aborted",
+            self.drained,
+            if self.drained == 1 { "" } else { "s" },
+            self.aborted
+        )
+    }
+}
+
+/// A handle to ask a running server to shut down gracefully: it stops
+/// accepting connections, finishes the requests it has, and closes the
+/// connections that are still busy once the deadline passes
+///
+/// # Examples
+///
+/// ```
+/// let server = server::Server::new(5);
+/// let stopper = server.stopper();
+///
+/// let handle = server.start_at("127.0.0.1:0", "config.txt");
+/// // From anywhere, like another thread
+/// stopper.stop();
+///
+/// let report = handle.join();
+/// assert_eq!(report.aborted, 0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Stopper {
+    requested: Arc<Mutex<Option<StopRequest>>>,
+    waker: Arc<Waker>,
+}
+
+/// A shutdown that was asked for, and how long requests get to finish
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct StopRequest {
+    /// None to use the `shutdown_timeout` of the config
+    pub(crate) timeout: Option<Duration>,
+}
+
+impl Stopper {
+    /// Function to create a handle for the server that waits on `waker`
+    pub(crate) fn new(waker: Arc<Waker>) -> Stopper {
+        Stopper {
+            requested: Arc::new(Mutex::new(None)),
+            waker,
+        }
+    }
+
+    /// Function to ask for a graceful shutdown, giving requests the
+    /// `shutdown_timeout` of the config to finish
+    pub fn stop(&self) {
+        self.request(None);
+    }
+
+    /// Function to ask for a graceful shutdown, giving requests `timeout` to
+    /// finish
+    pub fn stop_within(&self, timeout: Duration) {
+        self.request(Some(timeout));
+    }
+
+    fn request(&self, timeout: Option<Duration>) {
+        let mut requested = self.requested.lock().unwrap();
+        // Asking again can only bring the deadline forward
+        let timeout = match (*requested, timeout) {
+            (Some(StopRequest { timeout: Some(old) }), Some(new)) => {
+                Some(old.min(new))
+            }
+            (Some(StopRequest { timeout: Some(old) }), None) => Some(old),
+            (_, timeout) => timeout,
+        };
+        *requested = Some(StopRequest { timeout });
+        self.waker.wake();
+    }
+
+    /// Returns the shutdown that was asked for, if any. Once asked for, it
+    /// stays asked for
+    pub(crate) fn requested(&self) -> Option<StopRequest> {
+        *self.requested.lock().unwrap()
+    }
+}
+
+/// A handle to a running server, to wait for it or shut it down
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let server = server::Server::new(5);
+/// let handle = server.start_at("127.0.0.1:0", "config.txt");
+///
+/// let report = handle.shutdown(Duration::from_secs(10));
+/// println!("{}", report);
+/// ```
+pub struct ServerHandle {
+    thread: thread::JoinHandle<ShutdownReport>,
+    stopper: Stopper,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        thread: thread::JoinHandle<ShutdownReport>,
+        stopper: Stopper,
+    ) -> ServerHandle {
+        ServerHandle { thread, stopper }
+    }
+
+    /// Function to shut the server down gracefully, giving requests
+    /// `timeout` to finish, and wait until it has
+    ///
+    /// # Panics
+    ///
+    /// If the server thread panicked
+    pub fn shutdown(self, timeout: Duration) -> ShutdownReport {
+        self.stopper.stop_within(timeout);
+        self.join()
+    }
+
+    /// Function to wait until the server has shut down, which it does when
+    /// asked to by a `Stopper` or when `exit` is typed into the console
+    ///
+    /// # Panics
+    ///
+    /// If the server thread panicked
+    pub fn join(self) -> ShutdownReport {
+        match self.thread.join() {
+            Ok(report) => report,
+            Err(err) => panic::resume_unwind(err),
+        }
+    }
+
+    /// Returns a handle to shut the server down from elsewhere
+    pub fn stopper(&self) -> Stopper {
+        self.stopper.clone()
+    }
+}
+
+/// The connections a server has open, so they can be drained and closed
+/// when it shuts down
+#[derive(Default)]
+pub(crate) struct Connections {
+    state: Mutex<State>,
+    /// Told whenever a connection is closed or stops being busy
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    open: HashMap<u64, Open>,
+    stopping: bool,
+    report: ShutdownReport,
+}
+
+/// An open connection
+struct Open {
+    /// A handle to the connection, to close it from outside its worker
+    stream: TcpStream,
+    /// Whether a request is being handled
+    busy: bool,
+    /// Whether the connection was busy when the shutdown started, or became
+    /// busy after, and has not been answered yet
+    in_flight: bool,
+}
+
+impl Connections {
+    pub(crate) fn new() -> Arc<Connections> {
+        Arc::new(Connections::default())
+    }
+
+    /// Function to keep track of a newly accepted connection, which counts
+    /// as idle until its first request comes in
+    pub(crate) fn add(
+        self: &Arc<Self>,
+        stream: &TcpStream,
+    ) -> io::Result<Tracked> {
+        let stream = stream.try_clone()?;
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        let in_flight = state.stopping;
+        state.open.insert(
+            id,
+            Open {
+                stream,
+                busy: false,
+                in_flight,
+            },
+        );
+        Ok(Tracked {
+            connections: Arc::clone(self),
+            id,
+        })
+    }
+
+    /// Function to shut down gracefully. Idle connections are closed
+    /// straight away, and busy ones, or ones with a request waiting for a
+    /// worker, are given until `timeout` to be answered before they are
+    /// closed too
+    pub(crate) fn drain(&self, timeout: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        state.stopping = true;
+        for open in state.open.values_mut() {
+            if open.busy || has_request(&open.stream) {
+                open.in_flight = true;
+            } else {
+                open.stream.shutdown(Shutdown::Both).ok();
+            }
+        }
+        while state.open.values().any(|open| open.in_flight) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        let mut aborted = 0;
+        for open in state.open.values_mut() {
+            if open.in_flight {
+                open.in_flight = false;
+                aborted += 1;
+            }
+            open.stream.shutdown(Shutdown::Both).ok();
+        }
+        state.report.aborted += aborted;
+        state.report
+    }
+}
+
+/// A connection the server keeps track of, for as long as this is kept
+pub(crate) struct Tracked {
+    connections: Arc<Connections>,
+    id: u64,
+}
+
+impl Tracked {
+    /// Function to mark the connection as handling a request
+    pub(crate) fn busy(&self) {
+        let mut state = self.connections.state.lock().unwrap();
+        let stopping = state.stopping;
+        if let Some(open) = state.open.get_mut(&self.id) {
+            open.busy = true;
+            open.in_flight |= stopping;
+        }
+    }
+
+    /// Function to count the request being handled as answered, once its
+    /// response has been sent
+    pub(crate) fn answered(&self) {
+        let mut state = self.connections.state.lock().unwrap();
+        let in_flight = state
+            .open
+            .get_mut(&self.id)
+            .is_some_and(|open| mem::take(&mut open.in_flight));
+        if in_flight {
+            state.report.drained += 1;
+        }
+        self.connections.changed.notify_all();
+    }
+
+    /// Function to mark the connection as waiting for its next request.
+    /// Returns false if it should be closed instead, as the server is
+    /// shutting down
+    pub(crate) fn idle(&self) -> bool {
+        let mut state = self.connections.state.lock().unwrap();
+        let stopping = state.stopping;
+        if let Some(open) = state.open.get_mut(&self.id) {
+            open.busy = false;
+        }
+        self.connections.changed.notify_all();
+        !stopping
+    }
+
+    /// Returns whether the server is shutting down, in which case the
+    /// connection should not be kept alive
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.connections.state.lock().unwrap().stopping
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        let mut state = self.connections.state.lock().unwrap();
+        // Connections closed in the middle of a request never answered it
+        if state
+            .open
+            .remove(&self.id)
+            .is_some_and(|open| open.in_flight)
+        {
+            state.report.aborted += 1;
+        }
+        self.connections.changed.notify_all();
+    }
+}
+
+/// Returns whether a request has come in on a connection that no worker has
+/// read yet, without reading any of it
+#[cfg(unix)]
+fn has_request(stream: &TcpStream) -> bool {
+    use std::os::unix::io::AsRawFd;
+
+    let mut byte = 0u8;
+    // Safe as the buffer is one byte long
+    unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            &mut byte as *mut u8 as *mut libc::c_void,
+            1,
+            libc::MSG_PEEK | libc::MSG_DONTWAIT,
+        ) > 0
+    }
+}
+
+/// Returns whether a request has come in on a connection, which cannot be
+/// told here, so idle connections are closed even if one has
+#[cfg(not(unix))]
+fn has_request(_stream: &TcpStream) -> bool {
+    false
+}
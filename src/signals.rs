@@ -0,0 +1,148 @@
+/* See LICENSE for license details */
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+
+use crate::poll::{self, Waker};
+
+/// Set when the process is asked to shut down, and left set
+static STOPPING: AtomicBool = AtomicBool::new(false);
+/// Set when the process is asked to shut down, until the server sees it
+static STOP: AtomicBool = AtomicBool::new(false);
+/// Set when the process is asked to read its config again
+static RELOAD: AtomicBool = AtomicBool::new(false);
+/// Set when the process is asked to open its log files again
+static REOPEN: AtomicBool = AtomicBool::new(false);
+/// Where the handler writes to wake the server up, -1 for nowhere
+#[cfg(unix)]
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// What the process has been asked to do by signals since the last time it
+/// was checked
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Pending {
+    /// SIGTERM or SIGINT: shut down gracefully
+    pub(crate) stop: bool,
+    /// SIGHUP: read the config again
+    pub(crate) reload: bool,
+    /// SIGUSR1: open the access log again, after it has been rotated
+    pub(crate) reopen: bool,
+}
+
+/// Returns the signals that came in since this was last called
+pub(crate) fn pending() -> Pending {
+    Pending {
+        stop: STOP.swap(false, Ordering::SeqCst),
+        reload: RELOAD.swap(false, Ordering::SeqCst),
+        reopen: REOPEN.swap(false, Ordering::SeqCst),
+    }
+}
+
+/// Function to catch SIGTERM, SIGINT, SIGHUP and SIGUSR1 for the whole
+/// process, so they can be picked up with `pending()`. `waker` is woken
+/// whenever one comes in
+#[cfg(unix)]
+pub(crate) fn install(waker: &Arc<Waker>) -> io::Result<()> {
+    use std::{mem, ptr};
+
+    // The handler can be called at any time from now on, so the pipe it
+    // writes to is never closed
+    WAKE_FD.store(waker.write_fd(), Ordering::SeqCst);
+    mem::forget(Arc::clone(waker));
+
+    // Safe as the handler only touches atomics and errno, and calls write()
+    // and _exit(), all of which a signal handler may do. The action is set
+    // up before it is passed on
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle as extern "C" fn(libc::c_int) as usize;
+        // So reads (like the one for console input) carry on afterwards
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        for &signal in
+            &[libc::SIGTERM, libc::SIGINT, libc::SIGHUP, libc::SIGUSR1]
+        {
+            if libc::sigaction(signal, &action, ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Function to catch signals, which cannot be done here
+#[cfg(not(unix))]
+pub(crate) fn install(_waker: &Arc<Waker>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "signals can only be handled on unix",
+    ))
+}
+
+/// The signal handler. A second signal to shut down exits straight away, for
+/// when waiting for requests to finish takes too long
+#[cfg(unix)]
+extern "C" fn handle(signal: libc::c_int) {
+    // Waking the server can set errno, which the interrupted thread may be
+    // about to look at
+    let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+    match signal {
+        libc::SIGTERM | libc::SIGINT => {
+            if STOPPING.swap(true, Ordering::SeqCst) {
+                // Safe as _exit() may be called from a signal handler
+                unsafe { libc::_exit(1) };
+            }
+            STOP.store(true, Ordering::SeqCst);
+        }
+        libc::SIGHUP => RELOAD.store(true, Ordering::SeqCst),
+        libc::SIGUSR1 => REOPEN.store(true, Ordering::SeqCst),
+        _ => return,
+    }
+    let fd = WAKE_FD.load(Ordering::SeqCst);
+    if fd != -1 {
+        poll::wake_fd(fd);
+    }
+    set_errno(errno);
+}
+
+/// Function to set errno for the calling thread
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_errno(errno: libc::c_int) {
+    // Safe as the pointer is to errno of this thread, which is always there
+    unsafe { *libc::__errno_location() = errno };
+}
+
+/// Function to set errno for the calling thread
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+))]
+fn set_errno(errno: libc::c_int) {
+    // Safe as the pointer is to errno of this thread, which is always there
+    unsafe { *libc::__error() = errno };
+}
+
+/// Function to set errno for the calling thread
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+fn set_errno(errno: libc::c_int) {
+    // Safe as the pointer is to errno of this thread, which is always there
+    unsafe { *libc::__errno() = errno };
+}
+
+/// Function to set errno, which is not done where it is not known how
+#[cfg(all(
+    unix,
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd",
+        target_os = "netbsd"
+    ))
+))]
+fn set_errno(_errno: libc::c_int) {}
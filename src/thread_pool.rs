@@ -2,7 +2,11 @@
 use std::env;
 use std::io::{self, Write};
 use std::sync::{atomic, atomic::Ordering, mpsc, Arc, Mutex};
-use std::{thread, time, time::Duration};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::reload::Reloader;
+use crate::shutdown::Stopper;
 
 mod error_handler;
 
@@ -20,9 +24,18 @@ impl<F: FnOnce()> FnBox for F {
 
 pub type Job = Box<dyn FnBox + Send + 'static>;
 
+/// How often to look at whether the workers have stopped when there is a
+/// deadline to stop by
+const JOIN_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long idle workers get to notice they have been told to stop, even
+/// when the deadline has already passed
+const TERMINATE_GRACE: Duration = Duration::from_millis(100);
+
 enum Message {
     Terminate,
-    NewMessage(Job),
+    NewJob(Job),
+    #[allow(dead_code)]
     Nothing(String),
 }
 
@@ -80,16 +93,12 @@ impl ThreadPool {
         // has been shut off
         if self.is_dead.load(Ordering::Relaxed) {
             println!("Cannot execute");
-            return;
         } else {
             let job = Box::new(f);
             self.sender
-                .send(Message::NewMessage(job))
+                .send(Message::NewJob(job))
                 .unwrap_or_else(|err| {
-                    self.error.send(ErrorType::Fatal(String::from(format!(
-                        "{:?}",
-                        err
-                    ))));
+                    self.error.send(ErrorType::Fatal(format!("{:?}", err)));
                     self.is_dead.store(true, Ordering::Relaxed);
                 });
         }
@@ -99,31 +108,67 @@ impl ThreadPool {
     /// the is_dead variable to store the current state of the threadpool
     pub fn kill(&mut self) -> usize {
         if self.is_dead.load(Ordering::Relaxed) {
-            return 1;
+            1
         } else {
-            println!("Killing the workers");
-            for _ in &mut self.workers {
-                self.sender.send(Message::Terminate).ok();
-            }
-            for worker in &mut self.workers {
-                if let Some(thread) = worker.thread.take() {
+            self.stop(None);
+            0
+        }
+    }
+
+    /// Function to kill the threadpool, waiting for the workers only until
+    /// `deadline`. Workers still busy then are left running until the
+    /// process ends. Returns how many were left
+    pub fn kill_by(&mut self, deadline: Instant) -> usize {
+        if self.is_dead.load(Ordering::Relaxed) {
+            0
+        } else {
+            let grace = Instant::now() + TERMINATE_GRACE;
+            self.stop(Some(deadline.max(grace)))
+        }
+    }
+
+    /// Function to tell every worker to stop and wait for them, until
+    /// `deadline` if there is one. Returns how many workers were not waited
+    /// for
+    fn stop(&mut self, deadline: Option<Instant>) -> usize {
+        println!("Killing the workers");
+        for _ in &mut self.workers {
+            self.sender.send(Message::Terminate).ok();
+        }
+        let mut running = 0;
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                if deadline
+                    .is_some_and(|deadline| !finish_by(&thread, deadline))
+                {
+                    // Dropping the handle detaches the thread
+                    running += 1;
+                } else {
                     thread.join().unwrap();
                 }
             }
-            self.is_dead.store(true, Ordering::Relaxed);
-            return 0;
         }
+        self.is_dead.store(true, Ordering::Relaxed);
+        // The error thread only stops once it is told something fatal
+        // happened, which it passes on to the input thread too
+        self.error
+            .get_comms_sender()
+            .send(ErrorType::Fatal(String::from("Thread pool shut down")))
+            .ok();
+        running
     }
 
-    /// Function that returns the input thread that monitors user input. The
-    /// input thread has the ability to shut of all other threads via the
-    /// error handler that the thread pool uses.
+    /// Function that returns the input thread that monitors user input.
     ///
-    /// Sends the signal to shut off the server / threadpool with the command
-    /// `exit`
-    pub fn input(&mut self) -> thread::JoinHandle<()> {
+    /// Asks the server to shut down gracefully with the command `exit`, and
+    /// to read its config again with `reload`. Stops once there is no more
+    /// input
+    pub fn input(
+        &mut self,
+        reloader: Reloader,
+        stopper: Stopper,
+    ) -> thread::JoinHandle<()> {
         let err_recv = Arc::clone(&self._err_recv);
-        let comms_sender = self.error.get_comms_sender();
         let refer = Arc::clone(&self.is_dead);
         let thread = thread::Builder::new()
             .name("input_parser".to_string())
@@ -141,41 +186,42 @@ impl ThreadPool {
                     err_recv.lock().unwrap().try_recv().unwrap_or_else(|_| {
                         ErrorType::Nothing(String::from("Nothing"))
                     });
-                match msg {
-                    ErrorType::Fatal(_) => {
-                        println!("Server has died. Closing input thread");
-                        break;
-                    }
-                    _ => {}
-                };
+                if let ErrorType::Fatal(_) = msg {
+                    println!("Server has died. Closing input thread");
+                    break;
+                }
                 print!("> ");
                 // Have to flush the output with print! as it doesn't
                 // immediately print otherwise. I wish this was easier to do
                 io::stdout().flush().unwrap();
                 let mut user_input = String::new();
-                io::stdin().read_line(&mut user_input).unwrap();
+                match io::stdin().read_line(&mut user_input) {
+                    Ok(0) | Err(_) => {
+                        println!("No more input. Closing input thread");
+                        break;
+                    }
+                    Ok(_) => {}
+                }
                 if user_input.trim() == "exit" {
-                    // Send the error listener the call to shut down the server
+                    // The server shuts the thread pool down once it has
+                    // finished with its requests
                     println!("Server closing");
-                    comms_sender
-                        .send(ErrorType::Fatal(String::from(
-                            "User asked to quit",
-                        )))
-                        .unwrap();
-                    // 'refer' is the badly name reference to the boolean which
-                    // stores if the server is dead
-                    refer.store(true, Ordering::Relaxed);
+                    stopper.stop();
+                    break;
+                } else if user_input.trim() == "reload" {
+                    println!("Reloading the config");
+                    reloader.reload();
                 }
                 thread::sleep(Duration::from_millis(500));
             })
             .unwrap();
 
-        return thread;
+        thread
     }
 
     /// Accessor function to return if the threadpool has been shut off or not
     pub fn is_dead(&self) -> bool {
-        return self.is_dead.load(Ordering::Relaxed);
+        self.is_dead.load(Ordering::Relaxed)
     }
 }
 
@@ -191,6 +237,18 @@ impl Drop for ThreadPool {
     }
 }
 
+/// Function to wait for a thread to finish until `deadline`. Returns whether
+/// it did
+fn finish_by(thread: &thread::JoinHandle<()>, deadline: Instant) -> bool {
+    while !thread.is_finished() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(JOIN_INTERVAL);
+    }
+    true
+}
+
 impl Worker {
     /// Function to create a new Worker and return the handle to the worker's
     /// thread. This function cannot be used outside the threadpool as there
@@ -201,18 +259,20 @@ impl Worker {
         err_recv: Arc<Mutex<mpsc::Receiver<ErrorType>>>,
     ) -> Worker {
         let thread = thread::Builder::new()
-            .name(String::from(format!("worker_{}", id)))
+            .name(format!("worker_{}", id))
             .spawn(move || {
                 let is_debug = env::var("debug").is_ok();
                 loop {
-                    let msg =
-                        recv.lock().unwrap().try_recv().unwrap_or_else(|_| {
-                            Message::Nothing(String::from("Nothing"))
-                        });
+                    // Waits for a job, so it is started as soon as it is
+                    // sent. The pool is gone if nothing can be sent anymore
+                    let msg = match recv.lock().unwrap().recv() {
+                        Ok(msg) => msg,
+                        Err(_) => break,
+                    };
                     // Check if the worker got a job, which would be more
                     // important to do than to check if it has to die
                     match msg {
-                        Message::NewMessage(job) => {
+                        Message::NewJob(job) => {
                             if is_debug {
                                 println!("Worker {} got a job, executing", id);
                             }
@@ -233,7 +293,6 @@ impl Worker {
                         println!("Worker {} shutting down", id);
                         break;
                     }
-                    thread::sleep(time::Duration::from_millis(500));
                 }
             })
             .unwrap();
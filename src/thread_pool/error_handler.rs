@@ -12,6 +12,7 @@ pub enum ErrorType {
     #[allow(dead_code)]
     NonFatal(String),
     Fatal(String),
+    #[allow(dead_code)]
     Nothing(String),
 }
 
@@ -61,7 +62,6 @@ impl ErrorHandler {
                     format!("ERROR::NON_FATAL: {} at {}", err_non_fatal, time);
                 println!("{}", err_non_fatal);
                 let mut file = OpenOptions::new()
-                    .write(true)
                     .create(true)
                     .append(true)
                     .open("./logs/non_fatal.txt")
@@ -75,7 +75,6 @@ impl ErrorHandler {
                     format!("ERROR::FATAL: {} at {}", err_fatal, time);
                 println!("{}", err_fatal);
                 let mut file = OpenOptions::new()
-                    .write(true)
                     .create(true)
                     .append(true)
                     .open("./logs/fatal.txt")
@@ -141,22 +140,22 @@ impl ErrorHandler {
                 thread::sleep(Duration::from_millis(500));
             })
             .unwrap();
-        return thread;
+        thread
     }
 
     /// Accessor function for the error receiver
     pub fn get_err_recv(&self) -> Arc<Mutex<mpsc::Receiver<ErrorType>>> {
-        return Arc::clone(&self.err_receiver);
+        Arc::clone(&self.err_receiver)
     }
 
     /// Accessor function to get the sender for the input thread
     pub fn get_comms_sender(&self) -> mpsc::Sender<ErrorType> {
-        return mpsc::Sender::clone(&self.comms_sender);
+        mpsc::Sender::clone(&self.comms_sender)
     }
 
     /// I do not know what this is for
     #[allow(dead_code)]
     pub fn get_input_recv(&self) -> Arc<Mutex<mpsc::Receiver<ErrorType>>> {
-        return Arc::clone(&self.input_recv);
+        Arc::clone(&self.input_recv)
     }
 }